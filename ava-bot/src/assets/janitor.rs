@@ -0,0 +1,325 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{config::StorageConfig, storage::HistoryStore, AppState, DeviceId};
+
+use super::{parse_key, BlobMeta, BlobStore, ASSET_KINDS};
+
+/// unreferenced files younger than this may belong to a turn still running
+const GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Disk usage of the assets of a device.
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct DeviceUsage {
+    pub(crate) device_id: DeviceId,
+    pub(crate) files: usize,
+    pub(crate) bytes: u64,
+}
+
+/// What the janitor cleaned up in a sweep.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct SweepStats {
+    pub(crate) turns: usize,
+    pub(crate) files: usize,
+    pub(crate) bytes: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetentionPolicy {
+    /// unreferenced files older than it are removed, and turns if pruned
+    pub(crate) max_age: Option<Duration>,
+    /// max bytes of assets per device
+    pub(crate) quota: Option<u64>,
+    /// whether turns are removed too, otherwise only unreferenced files are
+    pub(crate) prune_turns: bool,
+}
+
+impl From<&StorageConfig> for RetentionPolicy {
+    fn from(config: &StorageConfig) -> Self {
+        Self {
+            max_age: (config.retention_days > 0)
+                .then(|| Duration::from_secs(config.retention_days * 24 * 3600)),
+            quota: (config.device_quota_mb > 0).then(|| config.device_quota_mb * 1024 * 1024),
+            prune_turns: config.prune_turns,
+        }
+    }
+}
+
+/// Sweep the assets periodically, until the server stops.
+pub async fn run_janitor(state: Arc<AppState>) {
+    let config = &state.config.storage;
+    if config.janitor_interval == 0 {
+        return;
+    }
+    let policy = RetentionPolicy::from(config);
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.janitor_interval));
+    loop {
+        ticker.tick().await;
+        let ret = sweep(
+            state.blobs.as_ref(),
+            state.history.as_ref(),
+            policy,
+            SystemTime::now(),
+        )
+        .await;
+        match ret {
+            Ok(stats) if stats != SweepStats::default() => info!(
+                "removed {} turns and {} files ({} bytes) of expired assets",
+                stats.turns, stats.files, stats.bytes
+            ),
+            Ok(_) => {}
+            Err(e) => warn!("failed to clean up assets: {}", e),
+        }
+    }
+}
+
+/// Storage usage of every device with assets, sorted by device_id.
+pub(crate) async fn usage(blobs: &dyn BlobStore) -> anyhow::Result<Vec<DeviceUsage>> {
+    let usage = scan(blobs)
+        .await?
+        .into_iter()
+        .map(|(device_id, blobs)| DeviceUsage {
+            device_id,
+            files: blobs.len(),
+            bytes: blobs.iter().map(|v| v.size).sum(),
+        })
+        .collect();
+    Ok(usage)
+}
+
+/// Remove the blobs no turn refers to once they're expired or the device is
+/// over quota. Turns are only removed if the policy prunes them: the expired
+/// ones first, then the oldest ones with their blobs while it's still over
+/// quota.
+pub(crate) async fn sweep(
+    blobs: &dyn BlobStore,
+    history: &dyn HistoryStore,
+    policy: RetentionPolicy,
+    now: SystemTime,
+) -> anyhow::Result<SweepStats> {
+    let mut stats = SweepStats::default();
+    if let Some(max_age) = policy.max_age.filter(|_| policy.prune_turns) {
+        let before = DateTime::<Utc>::from(now - max_age);
+        stats.turns += history.delete_turns_before(before)?;
+    }
+    let over_quota = |total: u64| policy.quota.is_some_and(|quota| total > quota);
+
+    for (device_id, mut files) in scan(blobs).await? {
+        let turns = history.list_turns(device_id, usize::MAX)?;
+        let referenced: HashSet<String> = turns
+            .iter()
+            .flat_map(|turn| turn.asset_urls())
+            .filter_map(|url| blobs.key(url))
+            .collect();
+
+        files.sort_by_key(|f| f.modified);
+        let mut total: u64 = files.iter().map(|f| f.size).sum();
+        let mut sizes = HashMap::new();
+        for file in files {
+            if referenced.contains(&file.key) {
+                sizes.insert(file.key, file.size);
+                continue;
+            }
+            let age = now.duration_since(file.modified).unwrap_or_default();
+            if age < GRACE_PERIOD {
+                continue;
+            }
+            // leftovers of interrupted writes
+            let is_tmp = file.key.ends_with(".tmp");
+            let expired = policy.max_age.is_some_and(|max_age| age > max_age);
+            if is_tmp || expired || over_quota(total) {
+                remove(blobs, &file.key, file.size, &mut stats).await?;
+                total -= file.size;
+            }
+        }
+
+        // referenced blobs only go away with their turns
+        if !policy.prune_turns {
+            continue;
+        }
+        for turn in &turns {
+            if !over_quota(total) {
+                break;
+            }
+            history.delete_turn(turn)?;
+            stats.turns += 1;
+            for key in turn.asset_urls().filter_map(|url| blobs.key(url)) {
+                if let Some(size) = sizes.remove(&key) {
+                    remove(blobs, &key, size, &mut stats).await?;
+                    total -= size;
+                }
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// All the asset blobs grouped by device_id.
+async fn scan(blobs: &dyn BlobStore) -> anyhow::Result<BTreeMap<DeviceId, Vec<BlobMeta>>> {
+    let mut devices: BTreeMap<DeviceId, Vec<BlobMeta>> = BTreeMap::new();
+    for kind in ASSET_KINDS {
+        for blob in blobs.list(&format!("{}/", kind)).await? {
+            if let Some((_, device_id)) = parse_key(&blob.key) {
+                devices.entry(device_id).or_default().push(blob);
+            }
+        }
+    }
+    Ok(devices)
+}
+
+async fn remove(
+    blobs: &dyn BlobStore,
+    key: &str,
+    size: u64,
+    stats: &mut SweepStats,
+) -> anyhow::Result<()> {
+    blobs.delete(key).await?;
+    stats.files += 1;
+    stats.bytes += size;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        assets::LocalBlobStore,
+        handlers::SpeechResult,
+        storage::{ChatTurn, SledHistoryStore},
+    };
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    struct Fixture {
+        root: PathBuf,
+        blobs: LocalBlobStore,
+        history: SledHistoryStore,
+        device_id: DeviceId,
+        now: SystemTime,
+    }
+
+    impl Fixture {
+        fn new() -> anyhow::Result<Self> {
+            let root = std::env::temp_dir().join(format!("ava-bot-test-{}", Uuid::new_v4()));
+            Ok(Self {
+                blobs: LocalBlobStore::new(&root),
+                root,
+                history: SledHistoryStore::temporary()?,
+                device_id: DeviceId::new(),
+                now: SystemTime::now(),
+            })
+        }
+
+        /// create an audio file of the device modified `age` ago, returns its url
+        fn file(&self, name: &str, size: usize, age: Duration) -> anyhow::Result<String> {
+            let key = format!("audio/{}/{}", self.device_id, name);
+            let path = self.root.join(&key);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, vec![0u8; size])?;
+            fs::File::options()
+                .write(true)
+                .open(&path)?
+                .set_modified(self.now - age)?;
+            Ok(self.blobs.url(&key))
+        }
+
+        fn turn(&self, id: &str, url: String) -> anyhow::Result<()> {
+            let reply = SpeechResult::new("hi", vec![url]);
+            self.history.save_turn(&ChatTurn::new(
+                id,
+                self.device_id,
+                "hello",
+                vec![reply.into()],
+            ))
+        }
+
+        fn exists(&self, url: &str) -> bool {
+            self.root.join(self.blobs.key(url).unwrap()).exists()
+        }
+
+        async fn sweep(&self, policy: RetentionPolicy) -> anyhow::Result<SweepStats> {
+            sweep(&self.blobs, &self.history, policy, self.now).await
+        }
+    }
+
+    #[tokio::test]
+    async fn sweep_should_keep_referenced_and_recent_files() -> anyhow::Result<()> {
+        let f = Fixture::new()?;
+        let referenced = f.file("referenced.mp3", 10, 48 * HOUR)?;
+        f.turn("1", referenced.clone())?;
+        let expired = f.file("expired.mp3", 10, 48 * HOUR)?;
+        let recent = f.file("recent.mp3", 10, Duration::ZERO)?;
+        let tmp = f.file("partial.tmp", 10, 2 * HOUR)?;
+
+        let policy = RetentionPolicy {
+            max_age: Some(24 * HOUR),
+            quota: None,
+            prune_turns: false,
+        };
+        let stats = f.sweep(policy).await?;
+        assert_eq!(stats.files, 2);
+        assert!(f.exists(&referenced));
+        assert!(f.exists(&recent));
+        assert!(!f.exists(&expired));
+        assert!(!f.exists(&tmp));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sweep_should_keep_turns_unless_pruned() -> anyhow::Result<()> {
+        let f = Fixture::new()?;
+        let old = f.file("old.mp3", 10, 48 * HOUR)?;
+        f.turn("1", old.clone())?;
+        let unreferenced = f.file("unreferenced.mp3", 10, 2 * HOUR)?;
+
+        let policy = RetentionPolicy {
+            max_age: Some(24 * HOUR),
+            quota: Some(5),
+            prune_turns: false,
+        };
+        let stats = f.sweep(policy).await?;
+        assert_eq!((stats.turns, stats.files), (0, 1));
+        assert!(f.exists(&old));
+        assert!(!f.exists(&unreferenced));
+        assert_eq!(f.history.list_turns(f.device_id, 10)?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sweep_should_drop_oldest_turns_over_quota() -> anyhow::Result<()> {
+        let f = Fixture::new()?;
+        let old = f.file("old.mp3", 10, 3 * HOUR)?;
+        f.turn("1", old.clone())?;
+        let new = f.file("new.mp3", 10, 2 * HOUR)?;
+        f.turn("2", new.clone())?;
+
+        let policy = RetentionPolicy {
+            max_age: None,
+            quota: Some(15),
+            prune_turns: true,
+        };
+        let stats = f.sweep(policy).await?;
+        assert_eq!(stats.turns, 1);
+        assert!(!f.exists(&old));
+        assert!(f.exists(&new));
+        let turns = f.history.list_turns(f.device_id, 10)?;
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].id, "2");
+
+        let usage = usage(&f.blobs).await?;
+        assert_eq!(usage.len(), 1);
+        assert_eq!((usage[0].files, usage[0].bytes), (1, 10));
+        Ok(())
+    }
+}
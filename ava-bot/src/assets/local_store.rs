@@ -0,0 +1,160 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::anyhow;
+use axum::async_trait;
+use bytes::Bytes;
+use tokio::fs;
+
+use super::{parse_key, served_key, served_url, BlobLocation, BlobMeta, BlobStore};
+
+/// Blobs kept as files under a directory, served by the app.
+#[derive(Debug)]
+pub(crate) struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+    pub(crate) fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        parse_key(key).ok_or_else(|| anyhow!("invalid asset key {}", key))?;
+        Ok(self.root.join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+    async fn put(&self, key: &str, data: Bytes, _content_type: &str) -> anyhow::Result<()> {
+        write_atomic(&self.path(key)?, data).await
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+        match fs::remove_file(&path).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        if let Some(parent) = path.parent() {
+            // only succeeds once the directory is empty
+            let _ = fs::remove_dir(parent).await;
+        }
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<BlobMeta>> {
+        // keys are at most two directories deep, walk from the deepest one
+        // the prefix names
+        let dir = match prefix.rfind('/') {
+            Some(i) => self.root.join(&prefix[..i]),
+            None => self.root.clone(),
+        };
+        let mut blobs = vec![];
+        let mut dirs = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                // nothing generated yet
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let meta = entry.metadata().await?;
+                let path = entry.path();
+                let Some(key) = relative_key(&self.root, &path) else {
+                    continue;
+                };
+                if meta.is_dir() {
+                    if key.split('/').count() < 3 {
+                        dirs.push(path);
+                    }
+                } else if key.starts_with(prefix) && parse_key(&key).is_some() {
+                    blobs.push(BlobMeta {
+                        key,
+                        size: meta.len(),
+                        modified: meta.modified()?,
+                    });
+                }
+            }
+        }
+        Ok(blobs)
+    }
+
+    async fn locate(&self, key: &str) -> anyhow::Result<BlobLocation> {
+        Ok(BlobLocation::File(self.path(key)?))
+    }
+
+    fn url(&self, key: &str) -> String {
+        served_url(key)
+    }
+
+    fn key(&self, url: &str) -> Option<String> {
+        served_key(url)
+    }
+}
+
+fn relative_key(root: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let parts: Option<Vec<&str>> = relative.iter().map(|v| v.to_str()).collect();
+    Some(parts?.join("/"))
+}
+
+/// Write the file through a temp file and a rename, so a server killed in the
+/// middle never leaves a half written asset behind.
+async fn write_atomic(path: &Path, data: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        // 父级路径没有创建就创建它
+        if !parent.exists() {
+            fs::create_dir_all(parent).await?
+        }
+    }
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, data).await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::{
+        assets::{audio_key, image_key},
+        DeviceId,
+    };
+
+    #[tokio::test]
+    async fn local_store_should_put_list_and_delete() -> anyhow::Result<()> {
+        let root = std::env::temp_dir().join(format!("ava-bot-test-{}", Uuid::new_v4()));
+        let store = LocalBlobStore::new(&root);
+        let device_id = DeviceId::new();
+        let audio = audio_key(device_id, "1");
+        store
+            .put(&audio, Bytes::from_static(b"mp3"), "audio/mpeg")
+            .await?;
+        store
+            .put(
+                &image_key(device_id, "2"),
+                Bytes::from_static(b"png"),
+                "image/png",
+            )
+            .await?;
+        assert!(store.put("../x", Bytes::new(), "").await.is_err());
+
+        let blobs = store.list("audio/").await?;
+        assert_eq!(blobs.len(), 1);
+        assert_eq!((blobs[0].key.as_str(), blobs[0].size), (audio.as_str(), 3));
+        assert_eq!(store.key(&store.url(&audio)).unwrap(), audio);
+
+        store.delete(&audio).await?;
+        assert!(store.list("audio/").await?.is_empty());
+        assert!(!root.join(format!("audio/{}", device_id)).exists());
+        Ok(())
+    }
+}
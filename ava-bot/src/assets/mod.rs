@@ -0,0 +1,126 @@
+mod janitor;
+mod local_store;
+mod s3_store;
+
+use std::{fmt::Debug, path::PathBuf, time::SystemTime};
+
+use axum::async_trait;
+use bytes::Bytes;
+use clap::ValueEnum;
+use serde::Deserialize;
+
+use crate::{config::StorageConfig, DeviceId};
+
+pub use janitor::run_janitor;
+pub(crate) use janitor::{usage, DeviceUsage};
+pub(crate) use local_store::LocalBlobStore;
+pub(crate) use s3_store::S3BlobStore;
+
+/// kinds of generated assets, each kept under `{kind}/{device_id}/`
+pub(crate) const ASSET_KINDS: [&str; 2] = ["audio", "image"];
+/// url prefix of the assets served by the app itself
+pub(crate) const ASSETS_URL_PREFIX: &str = "/assets";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlobBackend {
+    /// Files under assets_dir
+    Local,
+    /// An S3 compatible bucket, e.g. AWS S3 or MinIO
+    S3,
+}
+
+/// Where the generated audio and images are kept. Keys look like
+/// `{kind}/{device_id}/{name}`.
+#[async_trait]
+pub(crate) trait BlobStore: Debug + Send + Sync {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> anyhow::Result<()>;
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+
+    /// all the blobs whose key starts with `prefix`
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<BlobMeta>>;
+
+    /// where the blob behind an `/assets` url could be fetched
+    async fn locate(&self, key: &str) -> anyhow::Result<BlobLocation>;
+
+    /// url the page fetches the blob from
+    fn url(&self, key: &str) -> String;
+
+    /// key of a url returned by `url`
+    fn key(&self, url: &str) -> Option<String>;
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct BlobMeta {
+    pub(crate) key: String,
+    pub(crate) size: u64,
+    pub(crate) modified: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum BlobLocation {
+    File(PathBuf),
+    Redirect(String),
+}
+
+/// Open the backend selected by config.
+pub(crate) fn open_blob_store(config: &StorageConfig) -> anyhow::Result<Box<dyn BlobStore>> {
+    let store: Box<dyn BlobStore> = match config.backend {
+        BlobBackend::Local => Box::new(LocalBlobStore::new(&config.assets_dir)),
+        BlobBackend::S3 => Box::new(S3BlobStore::new(&config.s3)?),
+    };
+    Ok(store)
+}
+
+pub(crate) fn audio_key(device_id: DeviceId, name: &str) -> String {
+    format!("audio/{}/{}.mp3", device_id, name)
+}
+
+pub(crate) fn image_key(device_id: DeviceId, name: &str) -> String {
+    format!("image/{}/{}.png", device_id, name)
+}
+
+/// Kind and owner of a key, None if it's not a valid asset key.
+pub(crate) fn parse_key(key: &str) -> Option<(&str, DeviceId)> {
+    let mut parts = key.split('/');
+    let (kind, device_id, name) = (parts.next()?, parts.next()?, parts.next()?);
+    let device_id = device_id.parse().ok()?;
+    let is_valid = parts.next().is_none()
+        && ASSET_KINDS.contains(&kind)
+        && !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains('\\');
+    is_valid.then_some((kind, device_id))
+}
+
+/// url of a key served by the app, see `handlers::assets_handler`
+fn served_url(key: &str) -> String {
+    format!("{}/{}", ASSETS_URL_PREFIX, key)
+}
+
+fn served_key(url: &str) -> Option<String> {
+    let key = url.strip_prefix(ASSETS_URL_PREFIX)?.strip_prefix('/')?;
+    parse_key(key).map(|_| key.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_key_should_reject_invalid_keys() {
+        let id = DeviceId::new();
+        assert_eq!(parse_key(&audio_key(id, "1")), Some(("audio", id)));
+        assert_eq!(parse_key(&image_key(id, "1")), Some(("image", id)));
+        assert_eq!(parse_key(&format!("history/{}/conf", id)), None);
+        assert_eq!(parse_key(&format!("audio/{}/..", id)), None);
+        assert_eq!(parse_key(&format!("audio/{}/b/1.mp3", id)), None);
+        assert_eq!(parse_key("audio/../1.mp3"), None);
+        assert_eq!(parse_key("audio/a/1.mp3"), None);
+        let key = audio_key(id, "1");
+        assert_eq!(served_key(&served_url(&key)).unwrap(), key);
+        assert_eq!(served_key("/public/images/ava.png"), None);
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::SystemTime;
+
+use anyhow::{bail, Context};
+use axum::async_trait;
+use bytes::Bytes;
+use chrono::DateTime;
+use s3::{creds::Credentials, Bucket, Region};
+
+use crate::config::S3Config;
+
+use super::{served_key, served_url, BlobLocation, BlobMeta, BlobStore};
+
+/// Blobs kept in an S3 compatible bucket. Unless the bucket is published under
+/// `public_url`, the app serves the blobs by redirecting to presigned urls.
+#[derive(Debug)]
+pub(crate) struct S3BlobStore {
+    bucket: Bucket,
+    public_url: Option<String>,
+    url_expiry: u32,
+}
+
+impl S3BlobStore {
+    pub(crate) fn new(config: &S3Config) -> anyhow::Result<Self> {
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config.region.parse()?,
+        };
+        // falls back to the AWS_* env vars and profile when not configured
+        let credentials = Credentials::new(
+            config.access_key.as_deref(),
+            config.secret_key.as_deref(),
+            None,
+            None,
+            None,
+        )
+        .context("failed to load s3 credentials")?;
+        let mut bucket = Bucket::new(&config.bucket, region, credentials)?;
+        if config.path_style {
+            bucket = bucket.with_path_style();
+        }
+        Ok(Self {
+            bucket,
+            public_url: config
+                .public_url
+                .as_ref()
+                .map(|v| v.trim_end_matches('/').to_string()),
+            url_expiry: config.url_expiry,
+        })
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> anyhow::Result<()> {
+        let res = self
+            .bucket
+            .put_object_with_content_type(key, &data, content_type)
+            .await?;
+        if res.status_code() >= 300 {
+            bail!("failed to put {} to s3: {}", key, res.status_code());
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        self.bucket.delete_object(key).await?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> anyhow::Result<Vec<BlobMeta>> {
+        let pages = self.bucket.list(prefix.to_string(), None).await?;
+        pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| {
+                let modified = DateTime::parse_from_rfc3339(&object.last_modified)
+                    .with_context(|| format!("invalid last_modified of {}", object.key))?;
+                Ok(BlobMeta {
+                    key: object.key,
+                    size: object.size,
+                    modified: SystemTime::from(modified),
+                })
+            })
+            .collect()
+    }
+
+    async fn locate(&self, key: &str) -> anyhow::Result<BlobLocation> {
+        let url = self.bucket.presign_get(key, self.url_expiry, None)?;
+        Ok(BlobLocation::Redirect(url))
+    }
+
+    fn url(&self, key: &str) -> String {
+        match &self.public_url {
+            Some(base) => format!("{}/{}", base, key),
+            None => served_url(key),
+        }
+    }
+
+    fn key(&self, url: &str) -> Option<String> {
+        match &self.public_url {
+            Some(base) => url
+                .strip_prefix(base.as_str())
+                .and_then(|v| v.strip_prefix('/'))
+                .map(|v| v.to_string()),
+            None => served_key(url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use uuid::Uuid;
+
+    use super::*;
+
+    /// Run against a MinIO stand-in, e.g.
+    /// `docker run -p 9000:9000 minio/minio server /data` with a bucket `ava-test`,
+    /// then `AVA_TEST_S3_ENDPOINT=http://localhost:9000 cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore = "needs an s3 compatible endpoint at AVA_TEST_S3_ENDPOINT"]
+    async fn s3_store_should_put_list_and_delete() -> anyhow::Result<()> {
+        let config = S3Config {
+            bucket: "ava-test".to_string(),
+            endpoint: Some(env::var("AVA_TEST_S3_ENDPOINT")?),
+            access_key: Some(env::var("AVA_TEST_S3_ACCESS_KEY").unwrap_or("minioadmin".into())),
+            secret_key: Some(env::var("AVA_TEST_S3_SECRET_KEY").unwrap_or("minioadmin".into())),
+            path_style: true,
+            ..Default::default()
+        };
+        let store = S3BlobStore::new(&config)?;
+        let device_id = Uuid::new_v4().to_string();
+        let key = format!("audio/{}/1.mp3", device_id);
+        store
+            .put(&key, Bytes::from_static(b"mp3"), "audio/mpeg")
+            .await?;
+
+        let blobs = store.list(&format!("audio/{}/", device_id)).await?;
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].size, 3);
+        assert!(matches!(
+            store.locate(&key).await?,
+            BlobLocation::Redirect(_)
+        ));
+
+        store.delete(&key).await?;
+        assert!(store
+            .list(&format!("audio/{}/", device_id))
+            .await?
+            .is_empty());
+        Ok(())
+    }
+}
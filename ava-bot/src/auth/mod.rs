@@ -0,0 +1,216 @@
+mod oidc;
+mod sled_store;
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
+
+use anyhow::anyhow;
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        SaltString,
+    },
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use axum_extra::extract::CookieJar;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{AppState, DeviceId};
+
+pub(crate) use oidc::{OidcClient, OidcLogin};
+pub(crate) use sled_store::SledAccountStore;
+
+/// cookie carrying the session token of a signed in user
+pub(crate) const COOKIE_NAME_SESSION: &str = "session";
+/// how often the expired sessions are removed
+const SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Account {
+    pub(crate) id: Uuid,
+    pub(crate) username: String,
+    /// argon2 hash, None for accounts signed up through oidc
+    pub(crate) password_hash: Option<String>,
+    /// `sub` of the user at the oidc provider
+    pub(crate) oidc_subject: Option<String>,
+    /// devices the user signed in from, their history and assets belong to
+    /// the user
+    pub(crate) devices: Vec<DeviceId>,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Session {
+    pub(crate) account_id: Uuid,
+    pub(crate) expires_at: DateTime<Utc>,
+}
+
+/// Accounts, the devices linked to them and their sessions.
+pub(crate) trait AccountStore: Debug + Send + Sync {
+    /// fails if the username is taken
+    fn create_account(&self, account: &Account) -> anyhow::Result<()>;
+    fn get_account(&self, id: Uuid) -> anyhow::Result<Option<Account>>;
+    fn find_by_username(&self, username: &str) -> anyhow::Result<Option<Account>>;
+    fn find_by_oidc_subject(&self, subject: &str) -> anyhow::Result<Option<Account>>;
+    /// the account the device is linked to
+    fn device_account(&self, device_id: DeviceId) -> anyhow::Result<Option<Uuid>>;
+    fn link_device(&self, account_id: Uuid, device_id: DeviceId) -> anyhow::Result<()>;
+    fn save_session(&self, token: &str, session: &Session) -> anyhow::Result<()>;
+    /// None if the session doesn't exist or is expired
+    fn get_session(&self, token: &str) -> anyhow::Result<Option<Session>>;
+    fn delete_session(&self, token: &str) -> anyhow::Result<()>;
+    /// returns how many sessions are removed
+    fn delete_expired_sessions(&self, now: DateTime<Utc>) -> anyhow::Result<usize>;
+}
+
+/// A signed in user.
+#[derive(Debug, Clone)]
+pub struct User {
+    pub(crate) id: Uuid,
+    pub(crate) username: String,
+    pub(crate) devices: Vec<DeviceId>,
+}
+
+/// Who a request comes from.
+#[derive(Debug)]
+pub(crate) enum Auth {
+    /// a device not linked to any account
+    Anonymous,
+    User(User),
+    /// the device belongs to an account the request isn't signed in as
+    Denied,
+}
+
+impl Account {
+    pub(crate) fn new(username: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            username: username.into(),
+            password_hash: None,
+            oidc_subject: None,
+            devices: vec![],
+            created_at: Utc::now(),
+        }
+    }
+}
+
+impl From<Account> for User {
+    fn from(account: Account) -> Self {
+        Self {
+            id: account.id,
+            username: account.username,
+            devices: account.devices,
+        }
+    }
+}
+
+impl AppState {
+    /// Check the session cookie against the device. Devices are linked to the
+    /// account when the user signs in on them, or when the index page gives
+    /// a signed in user a new one.
+    pub(crate) fn authenticate(
+        &self,
+        jar: &CookieJar,
+        device_id: DeviceId,
+    ) -> anyhow::Result<Auth> {
+        let auth = match (
+            self.session_account(jar)?,
+            self.accounts.device_account(device_id)?,
+        ) {
+            (None, None) => Auth::Anonymous,
+            (Some(account), Some(owner)) if owner == account.id => Auth::User(account.into()),
+            _ => Auth::Denied,
+        };
+        Ok(auth)
+    }
+
+    /// The account signed in by the session cookie, None if there's no valid
+    /// session.
+    pub(crate) fn session_account(&self, jar: &CookieJar) -> anyhow::Result<Option<Account>> {
+        let session = match jar.get(COOKIE_NAME_SESSION) {
+            Some(cookie) => self.accounts.get_session(cookie.value())?,
+            None => None,
+        };
+        match session {
+            Some(session) => self.accounts.get_account(session.account_id),
+            None => Ok(None),
+        }
+    }
+
+    /// Start a session of the account, returns its token.
+    pub(crate) fn start_session(&self, account: &Account) -> anyhow::Result<String> {
+        let token = random_token();
+        let ttl = chrono::Duration::days(self.config.auth.session_ttl_days as i64);
+        let session = Session {
+            account_id: account.id,
+            expires_at: Utc::now() + ttl,
+        };
+        self.accounts.save_session(&token, &session)?;
+        Ok(token)
+    }
+}
+
+/// Remove the expired sessions periodically, until the server stops.
+pub async fn run_session_sweeper(state: Arc<AppState>) {
+    let mut ticker = tokio::time::interval(SESSION_SWEEP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        match state.accounts.delete_expired_sessions(Utc::now()) {
+            Ok(0) => {}
+            Ok(removed) => info!("removed {} expired sessions", removed),
+            Err(e) => warn!("failed to clean up sessions: {}", e),
+        }
+    }
+}
+
+/// Hash a password with argon2 and a random salt. It's slow on purpose, call
+/// it in a blocking task.
+pub(crate) fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow!("failed to hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub(crate) fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .and_then(|hash| Argon2::default().verify_password(password.as_bytes(), &hash))
+        .is_ok()
+}
+
+/// A hash no password is checked against, verified for unknown usernames so
+/// they take as long as a wrong password. It's slow to create the first time.
+pub(crate) fn dummy_password_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password(&random_token()).expect("dummy password shall hash"))
+}
+
+/// 32 random bytes in base64url, for session tokens and the like.
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_should_verify_against_its_hash() -> anyhow::Result<()> {
+        let hash = hash_password("correct horse")?;
+        assert!(hash.starts_with("$argon2"));
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("wrong horse", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{ensure, Context};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::sync::OnceCell;
+
+use crate::config::OidcConfig;
+
+use super::random_token;
+
+/// Signs users in with the authorization code flow of an OpenID Connect
+/// provider, with PKCE. The user is identified by the userinfo endpoint,
+/// fetched with the access token straight from the provider, once the ID
+/// token proves the code is of this sign in.
+#[derive(Debug)]
+pub(crate) struct OidcClient {
+    config: OidcConfig,
+    http: reqwest::Client,
+    // discovered on the first sign in, so startup doesn't depend on the provider
+    metadata: OnceCell<ProviderMetadata>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+}
+
+/// Secrets of a sign in, kept in a cookie from the redirect to the provider
+/// until the callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct OidcLogin {
+    /// passed back to the callback, against csrf
+    pub(crate) state: String,
+    /// proves the code is redeemed by who asked for it (PKCE)
+    code_verifier: String,
+    /// passed back in the ID token, against replayed tokens
+    nonce: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+/// Claims of the ID token checked by the sign in. The token comes straight
+/// from the token endpoint over tls, so its signature isn't checked.
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    aud: Value,
+    nonce: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct UserInfo {
+    pub(crate) sub: String,
+    preferred_username: Option<String>,
+    email: Option<String>,
+    name: Option<String>,
+}
+
+impl OidcClient {
+    pub(crate) fn new(config: OidcConfig) -> Self {
+        Self {
+            config,
+            http: reqwest::Client::new(),
+            metadata: OnceCell::new(),
+        }
+    }
+
+    /// name of the provider shown on the sign in page
+    pub(crate) fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    /// Where to send the browser to sign in.
+    pub(crate) async fn authorize_url(&self, login: &OidcLogin) -> anyhow::Result<String> {
+        let metadata = self.metadata().await?;
+        let mut url = Url::parse(&metadata.authorization_endpoint)?;
+        url.query_pairs_mut()
+            .append_pair("response_type", "code")
+            .append_pair("client_id", &self.config.client_id)
+            .append_pair("redirect_uri", &self.config.redirect_url)
+            .append_pair("scope", &self.config.scopes.join(" "))
+            .append_pair("state", &login.state)
+            .append_pair("code_challenge", &login.code_challenge())
+            .append_pair("code_challenge_method", "S256")
+            .append_pair("nonce", &login.nonce);
+        Ok(url.to_string())
+    }
+
+    /// Exchange the code given to the callback of the sign in for the user's
+    /// info.
+    pub(crate) async fn user_info(
+        &self,
+        code: &str,
+        login: &OidcLogin,
+    ) -> anyhow::Result<UserInfo> {
+        let metadata = self.metadata().await?;
+        let mut params = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", self.config.redirect_url.as_str()),
+            ("client_id", self.config.client_id.as_str()),
+            ("code_verifier", login.code_verifier.as_str()),
+        ];
+        if let Some(secret) = &self.config.client_secret {
+            params.push(("client_secret", secret.as_str()));
+        }
+        let token: TokenResponse = self
+            .http
+            .post(&metadata.token_endpoint)
+            .form(&params)
+            .send()
+            .await?
+            .error_for_status()
+            .context("failed to exchange the oidc code")?
+            .json()
+            .await?;
+        let id_token = token
+            .id_token
+            .context("the oidc provider returned no id token")?;
+        let claims = IdTokenClaims::decode(&id_token)?;
+        claims.check(&self.config.client_id, &login.nonce)?;
+        let info: UserInfo = self
+            .http
+            .get(&metadata.userinfo_endpoint)
+            .bearer_auth(token.access_token)
+            .send()
+            .await?
+            .error_for_status()
+            .context("failed to fetch the oidc userinfo")?
+            .json()
+            .await?;
+        ensure!(info.sub == claims.sub, "oidc userinfo is of another user");
+        Ok(info)
+    }
+
+    async fn metadata(&self) -> anyhow::Result<&ProviderMetadata> {
+        self.metadata
+            .get_or_try_init(|| async {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer_url.trim_end_matches('/')
+                );
+                let metadata = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await
+                    .with_context(|| format!("invalid oidc discovery document {}", url))?;
+                Ok::<_, anyhow::Error>(metadata)
+            })
+            .await
+    }
+}
+
+impl OidcLogin {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: random_token(),
+            code_verifier: random_token(),
+            nonce: random_token(),
+        }
+    }
+
+    /// Read it back from the cookie, None if it's not a valid one.
+    pub(crate) fn from_cookie(value: &str) -> Option<Self> {
+        let mut parts = value.split('.');
+        let login = Self {
+            state: parts.next()?.to_string(),
+            code_verifier: parts.next()?.to_string(),
+            nonce: parts.next()?.to_string(),
+        };
+        parts.next().is_none().then_some(login)
+    }
+
+    /// value of the cookie, the tokens are base64url which has no '.'
+    pub(crate) fn to_cookie(&self) -> String {
+        format!("{}.{}.{}", self.state, self.code_verifier, self.nonce)
+    }
+
+    fn code_challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.code_verifier.as_bytes()))
+    }
+}
+
+impl IdTokenClaims {
+    fn decode(id_token: &str) -> anyhow::Result<Self> {
+        let payload = id_token
+            .split('.')
+            .nth(1)
+            .context("invalid oidc id token")?;
+        let claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload)?)
+            .context("invalid oidc id token claims")?;
+        Ok(claims)
+    }
+
+    /// The token shall be issued to this client for this sign in.
+    fn check(&self, client_id: &str, nonce: &str) -> anyhow::Result<()> {
+        let audience = match &self.aud {
+            Value::String(v) => v == client_id,
+            Value::Array(v) => v.iter().any(|v| v == client_id),
+            _ => false,
+        };
+        ensure!(audience, "oidc id token is issued to another client");
+        ensure!(
+            self.nonce.as_deref() == Some(nonce),
+            "oidc id token is of another sign in"
+        );
+        Ok(())
+    }
+}
+
+impl UserInfo {
+    /// name of the account created for the user
+    pub(crate) fn username(&self) -> &str {
+        [&self.preferred_username, &self.email, &self.name]
+            .into_iter()
+            .flatten()
+            .find(|v| !v.is_empty())
+            .unwrap_or(&self.sub)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn oidc_login_should_round_trip_through_cookie() {
+        let login = OidcLogin::new();
+        assert_eq!(OidcLogin::from_cookie(&login.to_cookie()), Some(login));
+        assert_eq!(OidcLogin::from_cookie("a.b"), None);
+        assert_eq!(OidcLogin::from_cookie("a.b.c.d"), None);
+    }
+
+    #[test]
+    fn code_challenge_should_be_sha256_of_verifier() {
+        let login = OidcLogin {
+            state: "s".into(),
+            code_verifier: "correct-horse-battery-staple".into(),
+            nonce: "n".into(),
+        };
+        // base64url of the sha256, without padding
+        assert_eq!(
+            login.code_challenge(),
+            "h8vr_uvAX3xUrJM2xLS77IMSJ6ZBlRpL3n7dVgIPhZA"
+        );
+    }
+
+    #[test]
+    fn id_token_should_match_client_and_nonce() -> anyhow::Result<()> {
+        let token = |claims: Value| {
+            let payload = URL_SAFE_NO_PAD.encode(claims.to_string());
+            format!("eyJhbGciOiJSUzI1NiJ9.{}.sig", payload)
+        };
+        let claims = IdTokenClaims::decode(&token(json!({
+            "sub": "u1", "aud": ["ava", "other"], "nonce": "n1"
+        })))?;
+        assert_eq!(claims.sub, "u1");
+        claims.check("ava", "n1")?;
+        assert!(claims.check("ava", "n2").is_err());
+        assert!(claims.check("another", "n1").is_err());
+
+        let claims = IdTokenClaims::decode(&token(json!({"sub": "u1", "aud": "ava"})))?;
+        assert!(claims.check("ava", "n1").is_err());
+        assert!(IdTokenClaims::decode("not a token").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use uuid::Uuid;
+
+use crate::DeviceId;
+
+use super::{Account, AccountStore, Session};
+
+/// Accounts kept in trees of the history database.
+#[derive(Debug)]
+pub(crate) struct SledAccountStore {
+    // id => account
+    accounts: sled::Tree,
+    // lowercase username => id
+    usernames: sled::Tree,
+    // oidc subject => id
+    subjects: sled::Tree,
+    // device id => account id
+    devices: sled::Tree,
+    // token => session
+    sessions: sled::Tree,
+}
+
+impl SledAccountStore {
+    pub(crate) fn new(db: &sled::Db) -> anyhow::Result<Self> {
+        Ok(Self {
+            accounts: db.open_tree("accounts")?,
+            usernames: db.open_tree("usernames")?,
+            subjects: db.open_tree("oidc_subjects")?,
+            devices: db.open_tree("devices")?,
+            sessions: db.open_tree("sessions")?,
+        })
+    }
+
+    fn find_by_index(&self, index: &sled::Tree, key: &str) -> anyhow::Result<Option<Account>> {
+        match index.get(key)? {
+            Some(id) => self.get_account(Uuid::from_slice(&id)?),
+            None => Ok(None),
+        }
+    }
+
+    fn save_account(&self, account: &Account) -> anyhow::Result<()> {
+        self.accounts
+            .insert(account.id.as_bytes(), encode(account)?)?;
+        Ok(())
+    }
+}
+
+impl AccountStore for SledAccountStore {
+    fn create_account(&self, account: &Account) -> anyhow::Result<()> {
+        let username = account.username.to_lowercase();
+        let id = account.id.as_bytes().to_vec();
+        // the username is claimed atomically, so concurrent sign ups can't
+        // both get it
+        if self
+            .usernames
+            .compare_and_swap(&username, None as Option<&[u8]>, Some(id.clone()))?
+            .is_err()
+        {
+            bail!("username {} is taken", account.username);
+        }
+        if let Some(subject) = &account.oidc_subject {
+            self.subjects.insert(subject.as_str(), id)?;
+        }
+        self.save_account(account)?;
+        self.accounts.flush()?;
+        Ok(())
+    }
+
+    fn get_account(&self, id: Uuid) -> anyhow::Result<Option<Account>> {
+        self.accounts
+            .get(id.as_bytes())?
+            .map(|v| decode(&v))
+            .transpose()
+    }
+
+    fn find_by_username(&self, username: &str) -> anyhow::Result<Option<Account>> {
+        self.find_by_index(&self.usernames, &username.to_lowercase())
+    }
+
+    fn find_by_oidc_subject(&self, subject: &str) -> anyhow::Result<Option<Account>> {
+        self.find_by_index(&self.subjects, subject)
+    }
+
+    fn device_account(&self, device_id: DeviceId) -> anyhow::Result<Option<Uuid>> {
+        self.devices
+            .get(device_id.to_string())?
+            .map(|v| Ok(Uuid::from_slice(&v)?))
+            .transpose()
+    }
+
+    fn link_device(&self, account_id: Uuid, device_id: DeviceId) -> anyhow::Result<()> {
+        let Some(mut account) = self.get_account(account_id)? else {
+            bail!("account {} not found", account_id);
+        };
+        if !account.devices.contains(&device_id) {
+            account.devices.push(device_id);
+            self.save_account(&account)?;
+        }
+        self.devices
+            .insert(device_id.to_string(), &account_id.as_bytes()[..])?;
+        self.devices.flush()?;
+        Ok(())
+    }
+
+    fn save_session(&self, token: &str, session: &Session) -> anyhow::Result<()> {
+        self.sessions.insert(token, encode(session)?)?;
+        self.sessions.flush()?;
+        Ok(())
+    }
+
+    fn get_session(&self, token: &str) -> anyhow::Result<Option<Session>> {
+        let Some(value) = self.sessions.get(token)? else {
+            return Ok(None);
+        };
+        let session: Session = decode(&value)?;
+        if session.expires_at <= Utc::now() {
+            self.sessions.remove(token)?;
+            return Ok(None);
+        }
+        Ok(Some(session))
+    }
+
+    fn delete_session(&self, token: &str) -> anyhow::Result<()> {
+        self.sessions.remove(token)?;
+        self.sessions.flush()?;
+        Ok(())
+    }
+
+    fn delete_expired_sessions(&self, now: DateTime<Utc>) -> anyhow::Result<usize> {
+        let mut count = 0;
+        for item in self.sessions.iter() {
+            let (token, value) = item?;
+            let session: Session = decode(&value)?;
+            if session.expires_at <= now {
+                self.sessions.remove(token)?;
+                count += 1;
+            }
+        }
+        self.sessions.flush()?;
+        Ok(count)
+    }
+}
+
+fn encode<T: Serialize>(value: &T) -> anyhow::Result<Vec<u8>> {
+    Ok(serde_json::to_vec(value)?)
+}
+
+fn decode<T: DeserializeOwned>(value: &[u8]) -> anyhow::Result<T> {
+    Ok(serde_json::from_slice(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temporary() -> anyhow::Result<SledAccountStore> {
+        let db = sled::Config::new().temporary(true).open()?;
+        SledAccountStore::new(&db)
+    }
+
+    #[test]
+    fn account_store_should_keep_usernames_unique() -> anyhow::Result<()> {
+        let store = temporary()?;
+        let mut account = Account::new("Alice");
+        account.oidc_subject = Some("alice@sso".to_string());
+        store.create_account(&account)?;
+        assert!(store.create_account(&Account::new("alice")).is_err());
+
+        let found = store.find_by_username("ALICE")?.unwrap();
+        assert_eq!(found.id, account.id);
+        let found = store.find_by_oidc_subject("alice@sso")?.unwrap();
+        assert_eq!(found.id, account.id);
+        assert!(store.find_by_username("bob")?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn account_store_should_link_devices_and_expire_sessions() -> anyhow::Result<()> {
+        let store = temporary()?;
+        let account = Account::new("alice");
+        store.create_account(&account)?;
+        let device_id = DeviceId::new();
+        store.link_device(account.id, device_id)?;
+        store.link_device(account.id, device_id)?;
+        assert_eq!(store.device_account(device_id)?, Some(account.id));
+        assert_eq!(store.get_account(account.id)?.unwrap().devices, [device_id]);
+        assert!(store.device_account(DeviceId::new())?.is_none());
+
+        let now = Utc::now();
+        let session = |days| Session {
+            account_id: account.id,
+            expires_at: now + chrono::Duration::days(days),
+        };
+        store.save_session("valid", &session(1))?;
+        store.save_session("expired", &session(-1))?;
+        assert!(store.get_session("valid")?.is_some());
+        assert_eq!(store.delete_expired_sessions(now)?, 1);
+        assert!(store.get_session("expired")?.is_none());
+
+        store.delete_session("valid")?;
+        assert!(store.get_session("valid")?.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::info;
+
+use crate::{handlers::AssistantEvent, AppState, DeviceId};
+
+/// events a slow client could fall behind before it misses some
+const MAX_EVENTS: usize = 128;
+/// events kept for the clients reconnecting with `Last-Event-ID`, a turn with
+/// a long streamed reply takes a few hundreds
+const REPLAY_EVENTS: usize = 1024;
+/// how long the last event id of a removed channel is kept, the clock takes
+/// over after it
+const LAST_EVENT_ID_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// An event with its id, which increases by one for each event of a device.
+/// The ids of a new channel go on from the last one of the device, or from the
+/// clock in ms, so they keep increasing across the channels created for it.
+#[derive(Debug, Clone)]
+pub(crate) struct SequencedEvent {
+    pub(crate) id: u64,
+    pub(crate) event: AssistantEvent,
+}
+
+/// Events pushed to the clients of a device. The latest ones are kept, so a
+/// client reconnecting could get the ones it missed.
+#[derive(Debug)]
+pub(crate) struct EventChannel {
+    sender: broadcast::Sender<SequencedEvent>,
+    replay: Mutex<Replay>,
+    presence: Mutex<Presence>,
+}
+
+#[derive(Debug, Default)]
+struct Replay {
+    last_id: u64,
+    events: VecDeque<SequencedEvent>,
+}
+
+#[derive(Debug)]
+struct Presence {
+    subscribers: usize,
+    // whether any client has connected, the first one gets the events sent
+    // before it
+    subscribed: bool,
+    // when the last client disconnected, None while any is connected
+    idle_since: Option<Instant>,
+}
+
+/// The events a client receives, it's counted as connected until this is
+/// dropped.
+#[derive(Debug)]
+pub(crate) struct Subscription {
+    /// events after the last one the client got, to be sent first
+    pub(crate) missed: Vec<SequencedEvent>,
+    pub(crate) receiver: broadcast::Receiver<SequencedEvent>,
+    pub(crate) subscriber: Subscriber,
+}
+
+/// Counts a connected client in until dropped.
+#[derive(Debug)]
+pub(crate) struct Subscriber(Arc<EventChannel>);
+
+/// A device with clients connected.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct DevicePresence {
+    pub(crate) device_id: DeviceId,
+    pub(crate) connections: usize,
+}
+
+impl EventChannel {
+    /// A channel whose event ids go on after `last_id`.
+    pub(crate) fn after(last_id: u64) -> Self {
+        let (sender, _) = broadcast::channel(MAX_EVENTS);
+        Self {
+            sender,
+            replay: Mutex::new(Replay {
+                last_id,
+                events: VecDeque::new(),
+            }),
+            presence: Mutex::new(Presence {
+                subscribers: 0,
+                subscribed: false,
+                idle_since: Some(Instant::now()),
+            }),
+        }
+    }
+
+    /// Push an event to the connected clients. It's kept for replay even if
+    /// no client is connected.
+    pub(crate) fn send(&self, event: AssistantEvent) {
+        let mut replay = self.replay.lock().unwrap();
+        replay.last_id += 1;
+        let event = SequencedEvent {
+            id: replay.last_id,
+            event,
+        };
+        if replay.events.len() == REPLAY_EVENTS {
+            replay.events.pop_front();
+        }
+        replay.events.push_back(event.clone());
+        // sent with the lock held, so subscribers see the events in order
+        let _ = self.sender.send(event);
+    }
+
+    /// Receive the events from now on. With the id of the last event the
+    /// client got, the events after it which are still kept are sent first.
+    /// The first client ever connected gets all of them, as nobody got any.
+    pub(crate) fn subscribe(self: &Arc<Self>, last_id: Option<u64>) -> Subscription {
+        let mut presence = self.presence.lock().unwrap();
+        presence.subscribers += 1;
+        presence.idle_since = None;
+        let first = !std::mem::replace(&mut presence.subscribed, true);
+        drop(presence);
+        let last_id = last_id.or(first.then_some(0));
+
+        let replay = self.replay.lock().unwrap();
+        let missed = match last_id {
+            Some(last_id) => replay
+                .events
+                .iter()
+                .filter(|v| v.id > last_id)
+                .cloned()
+                .collect(),
+            None => vec![],
+        };
+        Subscription {
+            missed,
+            receiver: self.sender.subscribe(),
+            subscriber: Subscriber(self.clone()),
+        }
+    }
+
+    /// id of the last event sent
+    fn last_id(&self) -> u64 {
+        self.replay.lock().unwrap().last_id
+    }
+
+    /// number of clients connected
+    pub(crate) fn subscribers(&self) -> usize {
+        self.presence.lock().unwrap().subscribers
+    }
+
+    /// whether no client has been connected for `grace`
+    fn is_idle(&self, grace: Duration) -> bool {
+        let presence = self.presence.lock().unwrap();
+        presence
+            .idle_since
+            .is_some_and(|since| since.elapsed() >= grace)
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        let mut presence = self.0.presence.lock().unwrap();
+        presence.subscribers -= 1;
+        if presence.subscribers == 0 {
+            presence.idle_since = Some(Instant::now());
+        }
+    }
+}
+
+impl AppState {
+    /// The event channel of a device, created if it has none yet.
+    pub(crate) fn event_channel(&self, device_id: DeviceId) -> Arc<EventChannel> {
+        self.events
+            .entry(device_id)
+            .or_insert_with(|| {
+                let last_id = self.last_event_ids.remove(&device_id).map(|(_, (v, _))| v);
+                Arc::new(EventChannel::after(
+                    last_id.unwrap_or_default().max(clock_id()),
+                ))
+            })
+            .clone()
+    }
+
+    /// Devices with clients connected, and how many.
+    pub(crate) fn presence(&self) -> Vec<DevicePresence> {
+        self.events
+            .iter()
+            .map(|v| DevicePresence {
+                device_id: *v.key(),
+                connections: v.subscribers(),
+            })
+            .filter(|v| v.connections > 0)
+            .collect()
+    }
+
+    /// Remove the channels with no client connected for `grace`, unless a
+    /// turn is still sending to them. Returns how many are removed.
+    pub(crate) fn remove_idle_channels(&self, grace: Duration) -> usize {
+        let before = self.events.len();
+        // the map holds the only reference once nobody uses the channel
+        self.events.retain(|device_id, v| {
+            let keep = Arc::strong_count(v) > 1 || !v.is_idle(grace);
+            if !keep {
+                let last = (v.last_id(), Instant::now());
+                self.last_event_ids.insert(*device_id, last);
+            }
+            keep
+        });
+        before.saturating_sub(self.events.len())
+    }
+
+    /// Forget the last event ids of the channels removed more than `ttl` ago.
+    pub(crate) fn forget_event_ids(&self, ttl: Duration) {
+        self.last_event_ids
+            .retain(|_, (_, removed_at)| removed_at.elapsed() < ttl);
+    }
+}
+
+/// an id later than the ones of the channels created before, unless they sent
+/// more than an event per ms
+fn clock_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|v| v.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Remove idle event channels periodically, they're kept for the grace period
+/// so a client reconnecting could still get the events it missed.
+pub async fn run_channel_sweeper(state: Arc<AppState>) {
+    let grace = Duration::from_secs(state.config.server.channel_grace_period);
+    if grace.is_zero() {
+        return;
+    }
+    let mut ticker = tokio::time::interval((grace / 4).max(Duration::from_secs(1)));
+    loop {
+        ticker.tick().await;
+        let removed = state.remove_idle_channels(grace);
+        if removed > 0 {
+            info!("removed {} idle event channels", removed);
+        }
+        state.forget_event_ids(LAST_EVENT_ID_TTL);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        handlers::SignalEvent,
+        llm::{LlmProviders, MockProvider},
+        AppConfig,
+    };
+
+    #[test]
+    fn channel_should_replay_events_after_last_id() {
+        let channel = Arc::new(EventChannel::after(0));
+        for _ in 0..3 {
+            channel.send(SignalEvent::Complete.into());
+        }
+        let mut sub = channel.subscribe(Some(1));
+        let ids: Vec<_> = sub.missed.iter().map(|v| v.id).collect();
+        assert_eq!(ids, [2, 3]);
+
+        channel.send(SignalEvent::Complete.into());
+        assert_eq!(sub.receiver.try_recv().unwrap().id, 4);
+        assert!(channel.subscribe(None).missed.is_empty());
+    }
+
+    #[test]
+    fn first_subscriber_should_get_events_sent_before_it() {
+        let channel = Arc::new(EventChannel::after(7));
+        channel.send(SignalEvent::Complete.into());
+        let ids: Vec<_> = channel
+            .subscribe(None)
+            .missed
+            .iter()
+            .map(|v| v.id)
+            .collect();
+        assert_eq!(ids, [8]);
+        // a page loaded later starts afresh
+        assert!(channel.subscribe(None).missed.is_empty());
+    }
+
+    #[test]
+    fn channel_should_only_keep_latest_events() {
+        let channel = Arc::new(EventChannel::after(0));
+        for _ in 0..REPLAY_EVENTS + 10 {
+            channel.send(SignalEvent::Complete.into());
+        }
+        let missed = channel.subscribe(Some(0)).missed;
+        assert_eq!(missed.len(), REPLAY_EVENTS);
+        assert_eq!(missed[0].id, 11);
+    }
+
+    #[test]
+    fn channel_should_count_subscribers_until_dropped() {
+        let channel = Arc::new(EventChannel::after(0));
+        assert!(channel.is_idle(Duration::ZERO));
+        let a = channel.subscribe(None);
+        let b = channel.subscribe(None);
+        assert_eq!(channel.subscribers(), 2);
+        drop(a);
+        assert!(!channel.is_idle(Duration::ZERO));
+        drop(b);
+        assert_eq!(channel.subscribers(), 0);
+        assert!(channel.is_idle(Duration::ZERO));
+        assert!(!channel.is_idle(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn idle_channels_should_be_removed_unless_in_use() -> anyhow::Result<()> {
+        let mut config = AppConfig::default();
+        config.storage.db_path =
+            std::env::temp_dir().join(format!("ava-channel-test-{}", uuid::Uuid::new_v4()));
+        let llm = LlmProviders::from_provider(Arc::new(MockProvider::default()));
+        let state = AppState::with_llm(config, llm)?;
+        let (a, b, c) = (DeviceId::new(), DeviceId::new(), DeviceId::new());
+        let sub = state.event_channel(a).subscribe(None);
+        // a turn is still sending to b
+        let sending = state.event_channel(b);
+        state.event_channel(c);
+
+        assert_eq!(state.presence().len(), 1);
+        assert_eq!(state.presence()[0].device_id, a);
+        assert_eq!(state.remove_idle_channels(Duration::ZERO), 1);
+        assert!(!state.events.contains_key(&c));
+
+        drop((sub, sending));
+        assert!(state.presence().is_empty());
+        assert_eq!(state.remove_idle_channels(Duration::from_secs(60)), 0);
+        assert_eq!(state.remove_idle_channels(Duration::ZERO), 2);
+
+        // the ids go on where the removed channel stopped
+        let mut sub = state.event_channel(a).subscribe(None);
+        state.event_channel(a).send(SignalEvent::Complete.into());
+        let last_id = sub.receiver.try_recv()?.id;
+        drop(sub);
+        state.remove_idle_channels(Duration::ZERO);
+        let mut sub = state.event_channel(a).subscribe(Some(last_id));
+        assert!(sub.missed.is_empty());
+        state.event_channel(a).send(SignalEvent::Complete.into());
+        assert!(sub.receiver.try_recv()?.id > last_id);
+
+        // and are forgotten after a while
+        drop(sub);
+        state.remove_idle_channels(Duration::ZERO);
+        state.forget_event_ids(Duration::from_secs(60));
+        assert!(state.last_event_ids.contains_key(&a));
+        state.forget_event_ids(Duration::ZERO);
+        assert!(state.last_event_ids.is_empty());
+        Ok(())
+    }
+}
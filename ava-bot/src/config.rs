@@ -0,0 +1,645 @@
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use ipnet::IpNet;
+use llm_sdk::{
+    chat_completion::ChatCompleteModel,
+    speech::{SpeechModel, SpeechVoice},
+};
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    assets::BlobBackend,
+    llm::ProviderKind,
+    server::{cert_files, ServeMode},
+    Args,
+};
+
+/// config file loaded when `--config` isn't given, if it exists
+const DEFAULT_CONFIG_FILE: &str = "ava.toml";
+
+/// Settings of the whole app. Defaults are overridden by the config file, which
+/// is overridden by env vars and command line args.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub limits: LimitsConfig,
+    pub providers: ProvidersConfig,
+    pub openai: OpenAiConfig,
+    pub local: LocalConfig,
+    pub assistant: AssistantConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: IpAddr,
+    pub port: u16,
+    pub mode: ServeMode,
+    /// directory of cert.pem and key.pem
+    pub cert_path: PathBuf,
+    /// seconds between checks of cert changes in tls mode, 0 to disable
+    pub cert_reload_interval: u64,
+    /// proxies whose `X-Forwarded-*` headers are trusted, as ip or cidr
+    #[serde(deserialize_with = "deserialize_ip_nets")]
+    pub trusted_proxies: Vec<IpNet>,
+    /// static files served under /public
+    pub public_dir: PathBuf,
+    /// seconds to wait for running requests on shutdown
+    pub shutdown_timeout: u64,
+    /// seconds the events of a device are kept after its last client
+    /// disconnects, for it to reconnect without missing any, 0 keeps forever
+    pub channel_grace_period: u64,
+    /// bearer token of the /admin endpoints, which are disabled without it
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// where the generated audio and images are kept
+    pub backend: BlobBackend,
+    /// generated audio and images of the local backend
+    pub assets_dir: PathBuf,
+    /// conversation history database
+    pub db_path: PathBuf,
+    /// days to keep the assets no turn refers to, and the turns too with
+    /// `prune_turns`, 0 to keep forever
+    pub retention_days: u64,
+    /// max megabytes of assets per device, the ones no turn refers to are
+    /// removed beyond it, and the oldest turns too with `prune_turns`, 0 for
+    /// no limit
+    pub device_quota_mb: u64,
+    /// remove the turns expired or over the quota together with their assets,
+    /// off to keep the history
+    pub prune_turns: bool,
+    /// seconds between the cleanups of expired assets, 0 to disable
+    pub janitor_interval: u64,
+    pub s3: S3Config,
+}
+
+/// S3 compatible bucket of the s3 backend
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// endpoint of S3 compatible services like MinIO
+    pub endpoint: Option<String>,
+    /// the AWS_* env vars or profile are used if not set
+    pub access_key: Option<String>,
+    pub secret_key: Option<String>,
+    /// required by most S3 compatible services
+    pub path_style: bool,
+    /// base url of a public bucket or a CDN in front of it. Without it, the app
+    /// serves the assets by redirecting to presigned urls.
+    pub public_url: Option<String>,
+    /// seconds presigned urls are valid for
+    pub url_expiry: u32,
+}
+
+/// Sign in on top of the device cookies. Without `required`, anonymous devices
+/// could still use the assistant.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// only signed in users may use the assistant
+    pub required: bool,
+    /// sign in with username and password
+    pub local_login: bool,
+    /// anyone could create a local account
+    pub allow_signup: bool,
+    /// days a session stays valid
+    pub session_ttl_days: u64,
+    /// sign in with an OpenID Connect provider
+    pub oidc: Option<OidcConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OidcConfig {
+    /// shown on the sign in button
+    #[serde(default = "default_oidc_name")]
+    pub name: String,
+    /// `/.well-known/openid-configuration` is discovered under it
+    pub issuer_url: String,
+    pub client_id: String,
+    pub client_secret: Option<String>,
+    /// `https://<host>/auth/oidc/callback`, as registered at the provider
+    pub redirect_url: String,
+    #[serde(default = "default_oidc_scopes")]
+    pub scopes: Vec<String>,
+}
+
+/// Limits per signed in user, or per client address if not signed in. All off
+/// by default.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// turns started per minute, 0 for no limit
+    pub requests_per_minute: u32,
+    /// dollars spent per (UTC) day, 0 for no limit
+    pub daily_budget: f64,
+    pub prices: PricesConfig,
+}
+
+/// Dollars charged to the daily budget for the LLM calls.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PricesConfig {
+    /// per 1k prompt tokens of chat completion and tool calling
+    pub chat_input_per_1k: f64,
+    /// per 1k completion tokens
+    pub chat_output_per_1k: f64,
+    /// per minute of transcribed audio
+    pub transcription_per_minute: f64,
+    /// per 1k characters of speech
+    pub speech_per_1k_chars: f64,
+    /// per generated image
+    pub image: f64,
+}
+
+/// The provider used for each capability.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProvidersConfig {
+    pub chat: ProviderKind,
+    pub tools: ProviderKind,
+    pub transcription: ProviderKind,
+    pub speech: ProviderKind,
+    pub image: ProviderKind,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAiConfig {
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub max_retries: u32,
+}
+
+/// OpenAI compatible local endpoint
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalConfig {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssistantConfig {
+    /// name the assistant speaks as in system prompts
+    pub name: String,
+    /// model answering questions and writing code
+    pub chat_model: ChatCompleteModel,
+    /// model picking the tools
+    pub tools_model: ChatCompleteModel,
+    pub speech_model: SpeechModel,
+    pub speech_voice: SpeechVoice,
+    pub transcription_prompt: String,
+    pub tools_prompt: String,
+    pub answer_prompt: String,
+    pub write_code_prompt: String,
+    /// show what each turn used and cost below its replies
+    pub show_usage: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 8080,
+            mode: ServeMode::Tls,
+            cert_path: "./.certs".into(),
+            cert_reload_interval: 30,
+            trusted_proxies: vec![],
+            public_dir: "./public".into(),
+            shutdown_timeout: 30,
+            channel_grace_period: 60,
+            admin_token: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: BlobBackend::Local,
+            assets_dir: "/tmp/ava-bot".into(),
+            db_path: "/tmp/ava-bot/history".into(),
+            retention_days: 30,
+            device_quota_mb: 200,
+            prune_turns: false,
+            janitor_interval: 3600,
+            s3: S3Config::default(),
+        }
+    }
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            endpoint: None,
+            access_key: None,
+            secret_key: None,
+            path_style: false,
+            public_url: None,
+            url_expiry: 3600,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            required: false,
+            local_login: true,
+            allow_signup: true,
+            session_ttl_days: 30,
+            oidc: None,
+        }
+    }
+}
+
+fn default_oidc_name() -> String {
+    "SSO".to_string()
+}
+
+fn default_oidc_scopes() -> Vec<String> {
+    ["openid", "profile", "email"].map(String::from).to_vec()
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 0,
+            daily_budget: 0.0,
+            prices: PricesConfig::default(),
+        }
+    }
+}
+
+impl Default for PricesConfig {
+    fn default() -> Self {
+        Self {
+            chat_input_per_1k: 0.01,
+            chat_output_per_1k: 0.03,
+            transcription_per_minute: 0.006,
+            speech_per_1k_chars: 0.015,
+            image: 0.04,
+        }
+    }
+}
+
+impl Default for ProvidersConfig {
+    fn default() -> Self {
+        Self {
+            chat: ProviderKind::Openai,
+            tools: ProviderKind::Openai,
+            transcription: ProviderKind::Openai,
+            speech: ProviderKind::Openai,
+            image: ProviderKind::Openai,
+        }
+    }
+}
+
+impl Default for OpenAiConfig {
+    fn default() -> Self {
+        Self {
+            base_url: "https://api.openai.com/v1".to_string(),
+            api_key: None,
+            max_retries: 3,
+        }
+    }
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:11434/v1".to_string(),
+            api_key: None,
+            model: "llama2".to_string(),
+        }
+    }
+}
+
+impl Default for AssistantConfig {
+    fn default() -> Self {
+        Self {
+            name: "Ava".to_string(),
+            chat_model: ChatCompleteModel::default(),
+            tools_model: ChatCompleteModel::default(),
+            speech_model: SpeechModel::default(),
+            speech_voice: SpeechVoice::default(),
+            transcription_prompt: "If audio language is Chinese, please use Simplified Chinese".to_string(),
+            tools_prompt: "I can help to identify which tools to use, one or more tools could be used for a request. If no proper tool could be used, I'll directly reply the message with pure text. Once the tools have done the job, I'll reply with a brief summary or nothing.".to_string(),
+            answer_prompt: "I can help answer anything you'd like to chat".to_string(),
+            write_code_prompt: "I'm an expert on coding, I'll write code for you in markdown format based on your prompt".to_string(),
+            show_usage: false,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Load the config file given by args (or `ava.toml` if it exists), apply
+    /// the overrides of args and validate the result.
+    pub fn load(args: &Args) -> anyhow::Result<Self> {
+        let mut config = match &args.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Self::from_file(DEFAULT_CONFIG_FILE)?
+            }
+            None => Self::default(),
+        };
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    /// Args are either given on the command line or by env vars, both take
+    /// precedence over the config file.
+    pub fn apply_args(&mut self, args: &Args) {
+        fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+            if let Some(v) = value {
+                *target = v.clone();
+            }
+        }
+
+        set(&mut self.server.host, &args.host);
+        set(&mut self.server.port, &args.port);
+        set(&mut self.server.mode, &args.mode);
+        set(&mut self.server.cert_path, &args.cert_path);
+        set(&mut self.server.public_dir, &args.public_dir);
+        if !args.trusted_proxies.is_empty() {
+            self.server.trusted_proxies = args.trusted_proxies.clone();
+        }
+        set(&mut self.storage.backend, &args.storage_backend);
+        set(&mut self.storage.assets_dir, &args.assets_dir);
+        set(&mut self.storage.db_path, &args.db_path);
+        set(&mut self.auth.required, &args.auth_required);
+        if let (Some(oidc), Some(secret)) = (&mut self.auth.oidc, &args.oidc_client_secret) {
+            oidc.client_secret = Some(secret.clone());
+        }
+        set(&mut self.providers.chat, &args.chat_provider);
+        set(&mut self.providers.tools, &args.tools_provider);
+        set(
+            &mut self.providers.transcription,
+            &args.transcription_provider,
+        );
+        set(&mut self.providers.speech, &args.speech_provider);
+        set(&mut self.providers.image, &args.image_provider);
+        set(&mut self.openai.base_url, &args.openai_base_url);
+        if args.openai_api_key.is_some() {
+            self.openai.api_key = args.openai_api_key.clone();
+        }
+        set(&mut self.local.url, &args.local_url);
+        if args.admin_token.is_some() {
+            self.server.admin_token = args.admin_token.clone();
+        }
+        if args.local_api_key.is_some() {
+            self.local.api_key = args.local_api_key.clone();
+        }
+        set(&mut self.local.model, &args.local_model);
+    }
+
+    /// Catch misconfiguration at startup rather than on the first request.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.providers.uses(ProviderKind::Openai) {
+            if matches!(self.openai.api_key.as_deref(), None | Some("")) {
+                bail!("openai.api_key is required by the openai provider, set it in the config file or by OPENAI_API_KEY");
+            }
+            reqwest::Url::parse(&self.openai.base_url)
+                .with_context(|| format!("invalid openai.base_url {}", self.openai.base_url))?;
+        }
+        if self.providers.uses(ProviderKind::Local) {
+            reqwest::Url::parse(&self.local.url)
+                .with_context(|| format!("invalid local.url {}", self.local.url))?;
+            if self.local.model.is_empty() {
+                bail!("local.model is required by the local provider");
+            }
+        }
+        if self.server.mode == ServeMode::Tls {
+            let (cert, key) = cert_files(&self.server.cert_path);
+            if !cert.is_file() || !key.is_file() {
+                bail!(
+                    "{} and {} are required in tls mode, use `--mode http` behind a proxy terminating tls or `--mode self-signed` for development",
+                    cert.display(),
+                    key.display()
+                );
+            }
+        }
+        if self.storage.backend == BlobBackend::S3 && self.storage.s3.bucket.is_empty() {
+            bail!("storage.s3.bucket is required by the s3 backend");
+        }
+        if let Some(oidc) = &self.auth.oidc {
+            for (name, url) in [
+                ("issuer_url", &oidc.issuer_url),
+                ("redirect_url", &oidc.redirect_url),
+            ] {
+                reqwest::Url::parse(url)
+                    .with_context(|| format!("invalid auth.oidc.{} {}", name, url))?;
+            }
+            if oidc.client_id.is_empty() {
+                bail!("auth.oidc.client_id shall not be empty");
+            }
+        }
+        if self.auth.required && !self.auth.local_login && self.auth.oidc.is_none() {
+            bail!("auth.required needs auth.local_login or auth.oidc to sign in with");
+        }
+        let prices = &self.limits.prices;
+        let amounts = [
+            ("limits.daily_budget", self.limits.daily_budget),
+            ("limits.prices.chat_input_per_1k", prices.chat_input_per_1k),
+            (
+                "limits.prices.chat_output_per_1k",
+                prices.chat_output_per_1k,
+            ),
+            (
+                "limits.prices.transcription_per_minute",
+                prices.transcription_per_minute,
+            ),
+            (
+                "limits.prices.speech_per_1k_chars",
+                prices.speech_per_1k_chars,
+            ),
+            ("limits.prices.image", prices.image),
+        ];
+        for (name, amount) in amounts {
+            if !amount.is_finite() || amount < 0.0 {
+                bail!("{} shall be a non-negative number", name);
+            }
+        }
+        if !self.server.public_dir.is_dir() {
+            bail!(
+                "server.public_dir {} is not a directory",
+                self.server.public_dir.display()
+            );
+        }
+        if self.assistant.name.is_empty() {
+            bail!("assistant.name shall not be empty");
+        }
+        Ok(())
+    }
+}
+
+impl ProvidersConfig {
+    fn uses(&self, kind: ProviderKind) -> bool {
+        [
+            self.chat,
+            self.tools,
+            self.transcription,
+            self.speech,
+            self.image,
+        ]
+        .contains(&kind)
+    }
+}
+
+/// Parse an ip or a cidr, a plain ip is taken as a single address network.
+pub fn parse_ip_net(s: &str) -> anyhow::Result<IpNet> {
+    match s.parse::<IpNet>() {
+        Ok(net) => Ok(net),
+        Err(_) => {
+            let ip: IpAddr = s
+                .parse()
+                .with_context(|| format!("invalid ip or cidr {}", s))?;
+            Ok(ip.into())
+        }
+    }
+}
+
+fn deserialize_ip_nets<'de, D>(deserializer: D) -> Result<Vec<IpNet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let values = Vec::<String>::deserialize(deserializer)?;
+    values
+        .iter()
+        .map(|v| parse_ip_net(v).map_err(serde::de::Error::custom))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[test]
+    fn config_file_should_keep_defaults_of_missing_fields() {
+        let config: AppConfig = toml::from_str(
+            r#"
+            [server]
+            port = 3000
+
+            [providers]
+            chat = "local"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 3000);
+        assert_eq!(config.server.public_dir, Path::new("./public"));
+        assert_eq!(config.providers.chat, ProviderKind::Local);
+        assert_eq!(config.providers.tools, ProviderKind::Openai);
+        assert_eq!(config.openai.max_retries, 3);
+    }
+
+    #[test]
+    fn config_file_should_reject_unknown_fields() {
+        let ret = toml::from_str::<AppConfig>("[server]\nprot = 3000\n");
+        assert!(ret.is_err());
+    }
+
+    #[test]
+    fn args_should_override_config_file() {
+        let mut config: AppConfig = toml::from_str("[server]\nport = 3000\n").unwrap();
+        let args = Args::parse_from(["ava", "--port", "9000", "--local-model", "mistral"]);
+        config.apply_args(&args);
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.local.model, "mistral");
+        assert_eq!(config.local.url, "http://localhost:11434/v1");
+    }
+
+    #[test]
+    fn trusted_proxies_should_accept_ip_and_cidr() {
+        let config: AppConfig =
+            toml::from_str("[server]\ntrusted_proxies = [\"10.0.0.0/8\", \"::1\"]\n").unwrap();
+        let proxies = &config.server.trusted_proxies;
+        assert!(proxies[0].contains(&"10.1.2.3".parse::<IpAddr>().unwrap()));
+        assert_eq!(proxies[1], "::1/128".parse::<IpNet>().unwrap());
+    }
+
+    #[test]
+    fn validate_should_require_openai_api_key() {
+        let mut config = AppConfig::default();
+        config.server.mode = ServeMode::Http;
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("OPENAI_API_KEY"));
+
+        config.openai.api_key = Some("sk-test".to_string());
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_should_require_a_way_to_sign_in() {
+        let mut config: AppConfig = toml::from_str(
+            r#"
+            [server]
+            mode = "http"
+
+            [openai]
+            api_key = "sk-test"
+
+            [auth]
+            required = true
+            local_login = false
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        config.auth.oidc = Some(
+            toml::from_str(
+                r#"
+                issuer_url = "https://accounts.example.com"
+                client_id = "ava"
+                redirect_url = "https://ava.example.com/auth/oidc/callback"
+                "#,
+            )
+            .unwrap(),
+        );
+        assert!(config.validate().is_ok());
+        let oidc = config.auth.oidc.as_ref().unwrap();
+        assert_eq!(oidc.scopes, ["openid", "profile", "email"]);
+    }
+
+    #[test]
+    fn validate_should_require_certs_in_tls_mode() {
+        let mut config = AppConfig::default();
+        config.openai.api_key = Some("sk-test".to_string());
+        config.server.cert_path = "/not/exist".into();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("--mode http"));
+
+        config.server.mode = ServeMode::SelfSigned;
+        assert!(config.validate().is_ok());
+    }
+}
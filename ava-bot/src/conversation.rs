@@ -0,0 +1,138 @@
+use std::collections::VecDeque;
+
+use llm_sdk::chat_completion::ChatCompletionMessage;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{extractors::AppContext, DeviceId};
+
+/// max tokens of history we send along with each request
+const DEFAULT_TOKEN_BUDGET: usize = 3000;
+
+/// Whose conversation it is: a signed in user's follows them across the
+/// devices, an anonymous one stays with the device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum ConversationKey {
+    User(Uuid),
+    Device(DeviceId),
+}
+
+/// Running chat history of a user or device, oldest message first.
+#[derive(Debug, Clone)]
+pub(crate) struct Conversation {
+    messages: VecDeque<(usize, ChatCompletionMessage)>,
+    tokens: usize,
+    token_budget: usize,
+}
+
+impl Default for Conversation {
+    fn default() -> Self {
+        Self::new(DEFAULT_TOKEN_BUDGET)
+    }
+}
+
+impl Conversation {
+    pub(crate) fn new(token_budget: usize) -> Self {
+        Self {
+            messages: VecDeque::new(),
+            tokens: 0,
+            token_budget,
+        }
+    }
+
+    pub(crate) fn messages(&self) -> Vec<ChatCompletionMessage> {
+        self.messages.iter().map(|(_, m)| m.clone()).collect()
+    }
+
+    pub(crate) fn extend(&mut self, messages: impl IntoIterator<Item = ChatCompletionMessage>) {
+        for msg in messages {
+            let tokens = estimate_tokens(&msg);
+            self.tokens += tokens;
+            self.messages.push_back((tokens, msg));
+        }
+        self.truncate();
+    }
+
+    /// drop the oldest messages until the history fits into the token budget
+    fn truncate(&mut self) {
+        while self.tokens > self.token_budget {
+            let Some((tokens, _)) = self.messages.pop_front() else {
+                break;
+            };
+            self.tokens -= tokens;
+            // tool results are meaningless without the assistant message calling them
+            while let Some((tokens, ChatCompletionMessage::Tool(_))) = self.messages.front() {
+                self.tokens -= *tokens;
+                self.messages.pop_front();
+            }
+        }
+    }
+}
+
+impl From<&AppContext> for ConversationKey {
+    fn from(context: &AppContext) -> Self {
+        match &context.user {
+            Some(user) => ConversationKey::User(user.id),
+            None => ConversationKey::Device(context.device_id),
+        }
+    }
+}
+
+/// A rough estimation (~4 bytes per token) which is good enough for budgeting.
+pub(crate) fn estimate_tokens(msg: &ChatCompletionMessage) -> usize {
+    serde_json::to_string(msg)
+        .map(|s| estimate_text_tokens(&s))
+        .unwrap_or_default()
+}
+
+pub(crate) fn estimate_text_tokens(text: &str) -> usize {
+    text.len() / 4 + 1
+}
+
+pub(crate) fn assistant_message(content: impl Into<String>) -> ChatCompletionMessage {
+    serde_json::from_value(json!({
+        "role": "assistant",
+        "content": content.into(),
+    }))
+    .expect("assistant message shall be valid")
+}
+
+pub(crate) fn tool_message(
+    tool_call_id: impl Into<String>,
+    content: impl Into<String>,
+) -> ChatCompletionMessage {
+    serde_json::from_value(json!({
+        "role": "tool",
+        "tool_call_id": tool_call_id.into(),
+        "content": content.into(),
+    }))
+    .expect("tool message shall be valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversation_should_truncate_to_budget() {
+        let mut conv = Conversation::new(50);
+        conv.extend((0..10).map(|i| ChatCompletionMessage::new_user(format!("message {}", i), "")));
+        assert!(conv.tokens <= 50);
+        assert!(conv.messages.len() < 10);
+    }
+
+    #[test]
+    fn conversation_should_not_start_with_tool_result() {
+        let mut conv = Conversation::new(30);
+        conv.extend(vec![
+            ChatCompletionMessage::new_user("draw a cat with a very long prompt", ""),
+            tool_message("call_1", "a cute cat"),
+            assistant_message("done"),
+        ]);
+        assert_eq!(conv.messages.len(), 1);
+        assert!(matches!(
+            conv.messages().first(),
+            Some(ChatCompletionMessage::Assistant(_))
+        ));
+    }
+}
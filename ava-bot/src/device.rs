@@ -0,0 +1,72 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use uuid::{fmt::Hyphenated, Uuid};
+
+/// Id of a browser, kept in the `device_id` cookie. It names the directories
+/// and keys of the device's assets and history, so only the canonical
+/// hyphenated uuid form is accepted, which can't contain `/` or `..`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DeviceId(Uuid);
+
+impl DeviceId {
+    /// a new random id, for a browser without the cookie
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for DeviceId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FromStr for DeviceId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // uuid also parses the simple, braced and urn forms, which would give
+        // the same device several names
+        if s.len() != Hyphenated::LENGTH {
+            return Err(anyhow!("invalid device id: {}", s));
+        }
+        let uuid = Uuid::try_parse(s).map_err(|_| anyhow!("invalid device id: {}", s))?;
+        Ok(Self(uuid))
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.hyphenated())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_id_should_only_accept_hyphenated_uuid() {
+        let id = DeviceId::new();
+        assert_eq!(id.to_string().parse::<DeviceId>().unwrap(), id);
+        let upper = "67E55044-10B1-426F-9247-BB680E5FE0C8";
+        assert_eq!(
+            upper.parse::<DeviceId>().unwrap().to_string(),
+            upper.to_lowercase()
+        );
+
+        for v in [
+            "",
+            "a",
+            "../../etc",
+            "67e5504410b1426f9247bb680e5fe0c8",
+            "{67e55044-10b1-426f-9247-bb680e5fe0c8}",
+            "67e55044-10b1-426f-9247-bb680e5fe0c./",
+        ] {
+            assert!(v.parse::<DeviceId>().is_err(), "{}", v);
+        }
+    }
+}
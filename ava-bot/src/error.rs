@@ -1,17 +1,202 @@
+use std::fmt;
+
 use axum::{
-    http::StatusCode,
+    extract::multipart::MultipartError,
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
+    Json,
 };
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use tracing::warn;
+
+use crate::{extractors::Locale, handlers::ErrorSignal, limits::LimitExceeded};
+
+/// error codes of the LLM providers when a request is refused for its content
+const CONTENT_POLICY_CODES: [&str; 2] = ["content_policy_violation", "content_filter"];
+
+/// What went wrong, it decides the status code of the response and the
+/// message shown to the user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub(crate) enum ErrorKind {
+    BadInput,
+    Upstream,
+    RateLimited,
+    ContentPolicy,
+    Storage,
+    Internal,
+}
+
+/// Errors whose kind is known where they're raised. Rate limits are raised
+/// as `LimitExceeded`, anything unknown is an internal error.
+#[derive(Debug)]
+pub(crate) enum AssistantError {
+    /// the request can't be taken as is, shown to the user
+    BadInput(String),
+    /// an LLM provider failed
+    Upstream {
+        capability: &'static str,
+        source: anyhow::Error,
+    },
+    /// an LLM provider refused the request for its content
+    ContentPolicy(String),
+    /// the history or the generated assets can't be saved
+    Storage(anyhow::Error),
+}
 
+/// Returned by the handlers, rendered as a problem json (RFC 9457).
 pub struct AppError(anyhow::Error);
 
+#[derive(Debug, Serialize)]
+struct Problem {
+    #[serde(rename = "type")]
+    kind: String,
+    title: &'static str,
+    status: u16,
+    detail: String,
+}
+
+impl ErrorKind {
+    /// Find the kind of the outermost error of a known kind in the chain.
+    pub(crate) fn of(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if let Some(e) = cause.downcast_ref::<AssistantError>() {
+                return e.kind();
+            }
+            if cause.is::<LimitExceeded>() {
+                return ErrorKind::RateLimited;
+            }
+            if cause.is::<MultipartError>() {
+                return ErrorKind::BadInput;
+            }
+            if cause.is::<sled::Error>() {
+                return ErrorKind::Storage;
+            }
+        }
+        ErrorKind::Internal
+    }
+
+    pub(crate) fn status(self) -> StatusCode {
+        match self {
+            ErrorKind::BadInput => StatusCode::BAD_REQUEST,
+            ErrorKind::Upstream => StatusCode::BAD_GATEWAY,
+            ErrorKind::RateLimited => StatusCode::TOO_MANY_REQUESTS,
+            ErrorKind::ContentPolicy => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorKind::Storage => StatusCode::SERVICE_UNAVAILABLE,
+            ErrorKind::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ErrorKind::BadInput => "Bad input",
+            ErrorKind::Upstream => "Upstream failure",
+            ErrorKind::RateLimited => "Rate limited",
+            ErrorKind::ContentPolicy => "Content policy violation",
+            ErrorKind::Storage => "Storage failure",
+            ErrorKind::Internal => "Internal error",
+        }
+    }
+
+    pub(crate) fn message(self, locale: Locale) -> &'static str {
+        match (self, locale) {
+            (ErrorKind::BadInput, Locale::En) => "Sorry, I couldn't make sense of that request.",
+            (ErrorKind::BadInput, Locale::Zh) => "抱歉，无法理解这个请求。",
+            (ErrorKind::Upstream, Locale::En) => {
+                "The AI service isn't responding, please try again later."
+            }
+            (ErrorKind::Upstream, Locale::Zh) => "AI 服务暂时没有响应，请稍后再试。",
+            (ErrorKind::RateLimited, Locale::En) => "You've reached your usage limit.",
+            (ErrorKind::RateLimited, Locale::Zh) => "你已达到使用上限。",
+            (ErrorKind::ContentPolicy, Locale::En) => "Sorry, I can't help with that request.",
+            (ErrorKind::ContentPolicy, Locale::Zh) => "抱歉，无法处理这个请求。",
+            (ErrorKind::Storage, Locale::En) => "Couldn't save the conversation, please try again.",
+            (ErrorKind::Storage, Locale::Zh) => "无法保存对话，请重试。",
+            (ErrorKind::Internal, Locale::En) => "Something went wrong, please try again.",
+            (ErrorKind::Internal, Locale::Zh) => "出了点问题，请重试。",
+        }
+    }
+}
+
+impl AssistantError {
+    /// A failed request to the provider of `capability`, which is a content
+    /// policy rejection if the provider says so.
+    pub(crate) fn upstream(capability: &'static str, source: anyhow::Error) -> Self {
+        let msg = format!("{:#}", source);
+        if CONTENT_POLICY_CODES.iter().any(|code| msg.contains(code)) {
+            return AssistantError::ContentPolicy(msg);
+        }
+        AssistantError::Upstream { capability, source }
+    }
+
+    pub(crate) fn kind(&self) -> ErrorKind {
+        match self {
+            AssistantError::BadInput(_) => ErrorKind::BadInput,
+            AssistantError::Upstream { .. } => ErrorKind::Upstream,
+            AssistantError::ContentPolicy(_) => ErrorKind::ContentPolicy,
+            AssistantError::Storage(_) => ErrorKind::Storage,
+        }
+    }
+}
+
+impl AppError {
+    pub(crate) fn kind(&self) -> ErrorKind {
+        ErrorKind::of(&self.0)
+    }
+
+    /// The message shown to the user, in their language.
+    pub(crate) fn message(&self, locale: Locale) -> String {
+        match self.limit_exceeded() {
+            Some(e) => e.message(locale),
+            None => self.kind().message(locale).to_string(),
+        }
+    }
+
+    /// What's wrong with the input, internal errors are never shown.
+    pub(crate) fn detail(&self) -> Option<String> {
+        (self.kind() == ErrorKind::BadInput).then(|| self.0.to_string())
+    }
+
+    pub(crate) fn signal(&self, locale: Locale) -> ErrorSignal {
+        ErrorSignal {
+            kind: self.kind(),
+            message: self.message(locale),
+            detail: self.detail(),
+        }
+    }
+
+    fn limit_exceeded(&self) -> Option<&LimitExceeded> {
+        self.0.chain().find_map(|v| v.downcast_ref())
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
-        )
-            .into_response()
+        let kind = self.kind();
+        let status = kind.status();
+        if status.is_server_error() {
+            warn!("{}: {:#}", kind, self.0);
+        }
+        let problem = Problem {
+            kind: format!("urn:ava:error:{}", kind),
+            title: kind.title(),
+            status: status.as_u16(),
+            detail: self
+                .detail()
+                .unwrap_or_else(|| self.message(Locale::default())),
+        };
+        let mut res = (status, Json(problem)).into_response();
+        res.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/problem+json"),
+        );
+        if let Some(LimitExceeded::Rate { retry_after }) = self.limit_exceeded() {
+            res.headers_mut()
+                .insert(header::RETRY_AFTER, retry_after.as_secs().max(1).into());
+        }
+        res
     }
 }
 
@@ -23,3 +208,54 @@ where
         Self(err.into())
     }
 }
+
+impl fmt::Display for AssistantError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssistantError::BadInput(msg) => write!(f, "{}", msg),
+            AssistantError::Upstream { capability, source } => {
+                write!(f, "{} provider failed: {}", capability, source)
+            }
+            AssistantError::ContentPolicy(msg) => write!(f, "rejected by content policy: {}", msg),
+            AssistantError::Storage(source) => write!(f, "storage failed: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for AssistantError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AssistantError::Upstream { source, .. } | AssistantError::Storage(source) => {
+                Some(&**source)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn error_kind_should_be_found_in_the_chain() {
+        let err = anyhow::Error::from(AssistantError::BadInput("empty".into()))
+            .context("failed to read input");
+        assert_eq!(ErrorKind::of(&err), ErrorKind::BadInput);
+
+        let ret: Result<(), _> = Err(LimitExceeded::Budget);
+        let err = ret.context("turn refused").unwrap_err();
+        assert_eq!(ErrorKind::of(&err), ErrorKind::RateLimited);
+        assert_eq!(ErrorKind::of(&anyhow::anyhow!("oops")), ErrorKind::Internal);
+    }
+
+    #[test]
+    fn content_policy_rejection_should_be_told_from_upstream_failure() {
+        let err = anyhow::anyhow!("400: {{\"code\": \"content_policy_violation\"}}");
+        let err = AssistantError::upstream("image", err);
+        assert_eq!(err.kind(), ErrorKind::ContentPolicy);
+        let err = AssistantError::upstream("chat", anyhow::anyhow!("502 bad gateway"));
+        assert_eq!(err.kind(), ErrorKind::Upstream);
+    }
+}
@@ -1,34 +1,377 @@
+use std::{
+    convert::Infallible,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
 use axum::{
     async_trait,
-    extract::FromRequestParts,
-    http::{request::Parts, StatusCode},
+    body::Body,
+    extract::{ConnectInfo, FromRequest, FromRequestParts, Multipart},
+    http::{header, request::Parts, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
 };
 use axum_extra::extract::CookieJar;
+use bytes::Bytes;
+use ipnet::IpNet;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tracing::warn;
 
-use crate::COOKIE_NAME_DEVICE_ID;
+use crate::{auth::Auth, AppState, DeviceId, User, COOKIE_NAME_DEVICE_ID};
 
 #[derive(Debug, Clone)]
 pub struct AppContext {
-    pub(crate) device_id: String,
+    /// the browser, events are pushed to it
+    pub(crate) device_id: DeviceId,
+    /// None for anonymous devices
+    pub(crate) user: Option<User>,
+    /// address of the client, None if unknown
+    pub(crate) ip: Option<IpAddr>,
+}
+
+impl AppContext {
+    /// The context of the device with the cookies of `jar`, or why it's
+    /// refused.
+    pub(crate) fn resolve(
+        state: &AppState,
+        jar: &CookieJar,
+        device_id: DeviceId,
+        ip: Option<IpAddr>,
+    ) -> Result<Self, (StatusCode, &'static str)> {
+        let auth = state.authenticate(jar, device_id).map_err(|e| {
+            warn!("failed to authenticate {}: {}", device_id, e);
+            (StatusCode::INTERNAL_SERVER_ERROR, "failed to authenticate")
+        })?;
+        match auth {
+            Auth::User(user) => Ok(AppContext {
+                device_id,
+                user: Some(user),
+                ip,
+            }),
+            Auth::Anonymous if !state.config.auth.required => Ok(AppContext {
+                device_id,
+                user: None,
+                ip,
+            }),
+            Auth::Anonymous => Err((StatusCode::UNAUTHORIZED, "sign in required")),
+            Auth::Denied => Err((StatusCode::UNAUTHORIZED, "sign in again to use this device")),
+        }
+    }
+
+    /// whether the assets and history of the device belong to this request
+    pub(crate) fn owns(&self, device_id: DeviceId) -> bool {
+        device_id == self.device_id
+            || self
+                .user
+                .as_ref()
+                .is_some_and(|user| user.devices.contains(&device_id))
+    }
 }
 
 #[async_trait]
-impl<S> FromRequestParts<S> for AppContext
-where
-    S: Send + Sync,
-{
+impl FromRequestParts<Arc<AppState>> for AppContext {
     /// If the extractor fails it'll use this "rejection" type. A rejection is
     /// a kind of error that can be converted into a response.
     type Rejection = (StatusCode, &'static str);
 
     /// Perform the extraction.
-    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
         let jar = CookieJar::from_request_parts(parts, state).await.unwrap();
         let Some(device_id) = jar.get(COOKIE_NAME_DEVICE_ID) else {
             return Err((StatusCode::BAD_REQUEST, "cookie `device_id` is missing"));
         };
-        Ok(AppContext {
-            device_id: device_id.value().to_string(),
-        })
+        let Ok(device_id) = device_id.value().parse() else {
+            return Err((StatusCode::BAD_REQUEST, "cookie `device_id` is invalid"));
+        };
+        let Ok(client) = ClientInfo::from_request_parts(parts, state).await;
+        AppContext::resolve(state, &jar, device_id, client.ip)
+    }
+}
+
+/// Guards the /admin endpoints with the configured bearer token.
+#[derive(Debug, Clone)]
+pub struct AdminAuth;
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for AdminAuth {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let Some(token) = &state.config.server.admin_token else {
+            return Err((StatusCode::NOT_FOUND, "admin endpoints are disabled"));
+        };
+        let bearer = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "));
+        // digests of the same length compared in constant time, so the time
+        // taken tells nothing about the token
+        let matches = bearer.is_some_and(|v| {
+            Sha256::digest(v.as_bytes())
+                .ct_eq(&Sha256::digest(token.as_bytes()))
+                .into()
+        });
+        if matches {
+            Ok(AdminAuth)
+        } else {
+            Err((StatusCode::UNAUTHORIZED, "invalid admin token"))
+        }
+    }
+}
+
+/// Input of the assistant, either a multipart form with an `audio` or `text`
+/// field, or a json body like `{"text": "..."}`.
+pub enum AssistantInput {
+    Multipart(Multipart),
+    Text(String),
+    /// a recording streamed over the websocket, and its length in seconds if
+    /// the client tells it
+    Audio(Bytes, Option<f64>),
+}
+
+#[derive(Debug, Deserialize)]
+struct TextInput {
+    text: String,
+}
+
+#[async_trait]
+impl<S> FromRequest<S, Body> for AssistantInput
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/json"));
+
+        if is_json {
+            let Json(input) = Json::<TextInput>::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self::Text(input.text))
+        } else {
+            let data = Multipart::from_request(req, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            Ok(Self::Multipart(data))
+        }
+    }
+}
+
+/// Where a request comes from. `X-Forwarded-*` headers are only taken into
+/// account when the peer is a trusted proxy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// None if the peer address is unknown, e.g. in tests
+    pub ip: Option<IpAddr>,
+    pub scheme: String,
+    pub host: Option<String>,
+}
+
+#[async_trait]
+impl FromRequestParts<Arc<AppState>> for ClientInfo {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<AppState>,
+    ) -> Result<Self, Self::Rejection> {
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|v| v.0.ip());
+        let server = &state.config.server;
+        Ok(ClientInfo::resolve(
+            peer,
+            &parts.headers,
+            &server.trusted_proxies,
+            server.mode.scheme(),
+        ))
+    }
+}
+
+impl ClientInfo {
+    pub(crate) fn is_https(&self) -> bool {
+        self.scheme == "https"
+    }
+
+    fn resolve(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNet], scheme: &str) -> Self {
+        let is_trusted = |ip: &IpAddr| trusted.iter().any(|net| net.contains(ip));
+        let host = header_value(headers, header::HOST.as_str());
+        let Some(peer) = peer.filter(is_trusted) else {
+            return Self {
+                ip: peer,
+                scheme: scheme.to_string(),
+                host,
+            };
+        };
+
+        // walk back from the nearest hop, the first untrusted one is the client
+        let forwarded: Vec<IpAddr> = headers
+            .get_all("x-forwarded-for")
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .filter_map(|v| v.trim().parse().ok())
+            .collect();
+        let ip = forwarded
+            .iter()
+            .rev()
+            .find(|ip| !is_trusted(ip))
+            .or(forwarded.first())
+            .copied()
+            .unwrap_or(peer);
+
+        Self {
+            ip: Some(ip),
+            scheme: header_value(headers, "x-forwarded-proto")
+                .unwrap_or_else(|| scheme.to_string()),
+            host: header_value(headers, "x-forwarded-host").or(host),
+        }
+    }
+}
+
+/// Language of the messages shown to the user, the one they prefer the most
+/// by `Accept-Language`, English if none is supported.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Locale {
+    #[default]
+    En,
+    Zh,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Locale
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let value = parts
+            .headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        Ok(Locale::from_accept_language(value))
+    }
+}
+
+impl Locale {
+    fn from_accept_language(value: &str) -> Self {
+        let mut best = (0.0, Locale::default());
+        for item in value.split(',') {
+            let mut params = item.split(';');
+            let tag = params.next().unwrap_or_default().trim();
+            let q: f32 = params
+                .find_map(|v| v.trim().strip_prefix("q="))
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(1.0);
+            let lang = tag.split('-').next().unwrap_or_default();
+            let locale = if lang.eq_ignore_ascii_case("en") {
+                Locale::En
+            } else if lang.eq_ignore_ascii_case("zh") {
+                Locale::Zh
+            } else {
+                continue;
+            };
+            if q > best.0 {
+                best = (q, locale);
+            }
+        }
+        best.1
+    }
+}
+
+/// first value of a header, proxies may append theirs after a comma
+fn header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn client_info_should_ignore_forwarded_headers_of_untrusted_peer() {
+        let headers = headers(&[
+            ("host", "ava.ai"),
+            ("x-forwarded-for", "1.2.3.4"),
+            ("x-forwarded-proto", "https"),
+        ]);
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        let info =
+            ClientInfo::resolve(Some("5.6.7.8".parse().unwrap()), &headers, &trusted, "http");
+        assert_eq!(info.ip, Some("5.6.7.8".parse().unwrap()));
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host.as_deref(), Some("ava.ai"));
+    }
+
+    #[test]
+    fn client_info_should_use_forwarded_headers_of_trusted_proxy() {
+        let headers = headers(&[
+            ("host", "backend:8080"),
+            ("x-forwarded-for", "6.6.6.6, 1.2.3.4"),
+            ("x-forwarded-for", "10.0.0.2"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "ava.ai"),
+        ]);
+        let trusted = ["10.0.0.0/8".parse().unwrap()];
+        let info = ClientInfo::resolve(
+            Some("10.0.0.1".parse().unwrap()),
+            &headers,
+            &trusted,
+            "http",
+        );
+        // 6.6.6.6 is set by the client itself thus not trusted
+        assert_eq!(info.ip, Some("1.2.3.4".parse().unwrap()));
+        assert!(info.is_https());
+        assert_eq!(info.host.as_deref(), Some("ava.ai"));
+    }
+
+    #[test]
+    fn locale_should_be_the_most_preferred_supported_language() {
+        assert_eq!(
+            Locale::from_accept_language("zh-CN,zh;q=0.9,en;q=0.8"),
+            Locale::Zh
+        );
+        assert_eq!(
+            Locale::from_accept_language("fr-FR, en;q=0.5, zh-TW;q=0.8"),
+            Locale::Zh
+        );
+        assert_eq!(
+            Locale::from_accept_language("fr-FR,en-US;q=0.5"),
+            Locale::En
+        );
+        assert_eq!(Locale::from_accept_language(""), Locale::En);
     }
 }
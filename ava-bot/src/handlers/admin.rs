@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    assets::{usage, DeviceUsage},
+    channel::DevicePresence,
+    error::AppError,
+    extractors::AdminAuth,
+    ledger::ToolUsage,
+    AppState,
+};
+
+/// days of tool usage reported by default
+const DEFAULT_USAGE_DAYS: u32 = 30;
+/// a year at most, so the start date can't overflow
+const MAX_USAGE_DAYS: u32 = 366;
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    /// report the tool usage of the last `days` days, today included, at most
+    /// a year
+    days: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct UsageReport {
+    days: u32,
+    tools: Vec<ToolReport>,
+    spend_today: Vec<SpendReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct ToolReport {
+    tool: String,
+    #[serde(flatten)]
+    usage: ToolUsage,
+}
+
+#[derive(Debug, Serialize)]
+struct SpendReport {
+    key: String,
+    spent_today: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct PresenceReport {
+    online: usize,
+    connections: usize,
+    devices: Vec<DevicePresence>,
+}
+
+#[derive(Debug, Serialize)]
+struct StorageReport {
+    files: usize,
+    bytes: u64,
+    devices: Vec<DeviceUsage>,
+}
+
+/// Devices with a page connected for events, and how many pages each.
+pub async fn admin_presence_handler(
+    _: AdminAuth,
+    State(state): State<Arc<AppState>>,
+) -> impl IntoResponse {
+    let devices = state.presence();
+    Json(PresenceReport {
+        online: devices.len(),
+        connections: devices.iter().map(|v| v.connections).sum(),
+        devices,
+    })
+}
+
+/// Storage usage of the generated assets, per device.
+pub async fn admin_storage_handler(
+    _: AdminAuth,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let devices = usage(state.blobs.as_ref()).await?;
+    Ok(Json(StorageReport {
+        files: devices.iter().map(|v| v.files).sum(),
+        bytes: devices.iter().map(|v| v.bytes).sum(),
+        devices,
+    }))
+}
+
+/// What each tool used over the last days, and the dollars spent today by
+/// each user and anonymous client.
+pub async fn admin_usage_handler(
+    _: AdminAuth,
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<UsageQuery>,
+) -> Result<impl IntoResponse, AppError> {
+    let days = query
+        .days
+        .unwrap_or(DEFAULT_USAGE_DAYS)
+        .clamp(1, MAX_USAGE_DAYS);
+    let since = Utc::now().date_naive() - Duration::days(days as i64 - 1);
+    let tools = state.usage.by_tool(since)?;
+    let spend = state.limits.spent_today_by_key()?;
+    Ok(Json(UsageReport {
+        days,
+        tools: tools
+            .into_iter()
+            .map(|(tool, usage)| ToolReport { tool, usage })
+            .collect(),
+        spend_today: spend
+            .into_iter()
+            .map(|(key, spent_today)| SpendReport { key, spent_today })
+            .collect(),
+    }))
+}
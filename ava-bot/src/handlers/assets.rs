@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{boxed, Body},
+    extract::{Path, State},
+    http::{header, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Redirect, Response},
+};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::{
+    assets::{parse_key, BlobLocation},
+    error::AppError,
+    extractors::AppContext,
+    AppState,
+};
+
+/// Serve a generated audio or image to the device owning it.
+pub async fn assets_handler(
+    context: AppContext,
+    State(state): State<Arc<AppState>>,
+    Path((kind, device_id, name)): Path<(String, String, String)>,
+    req: Request<Body>,
+) -> Result<Response, AppError> {
+    let key = format!("{}/{}/{}", kind, device_id, name);
+    let Some((_, owner)) = parse_key(&key) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if !context.owns(owner) {
+        return Ok((StatusCode::FORBIDDEN, "asset belongs to another user").into_response());
+    }
+
+    let mut res = match state.blobs.locate(&key).await? {
+        BlobLocation::File(path) => ServeFile::new(path).oneshot(req).await?.map(boxed),
+        // a short lived presigned url, checked the owner already
+        BlobLocation::Redirect(url) => Redirect::temporary(&url).into_response(),
+    };
+    // only the owner may get it, shared caches shall not keep it
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("private"));
+    Ok(res)
+}
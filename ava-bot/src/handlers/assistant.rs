@@ -1,79 +1,169 @@
 use comrak::markdown_to_html;
-use std::{str::FromStr, sync::Arc};
-use tokio::{fs, sync::broadcast};
+use futures::{channel::mpsc, stream, Stream, StreamExt};
+use std::{pin::pin, sync::Arc};
 
 use anyhow::{anyhow, bail};
 use axum::{
-    extract::{Multipart, State},
-    response::IntoResponse,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
     Json,
 };
-use base64::{engine::general_purpose::STANDARD, Engine as _};
+use bytes::Bytes;
 use llm_sdk::{
-    chat_completion::{ChatCompletionChoice, ChatCompletionMessage, ChatCompletionRequest},
-    create_image::{CreateImageRequestBuilder, ImageResponseFormat},
-    speech::SpeechRequest,
+    chat_completion::{
+        ChatCompleteModel, ChatCompletionChoice, ChatCompletionMessage,
+        ChatCompletionRequestBuilder, Tool,
+    },
+    speech::SpeechRequestBuilder,
     whisper::{WhisperRequestBuilder, WhisperRequestType},
-    LlmSdk,
 };
 use serde_json::json;
+use tracing::{info_span, instrument, warn, Instrument};
 use uuid::Uuid;
 
 use crate::{
-    audio_path, audio_url,
-    error::AppError,
-    extractors::AppContext,
-    image_path, image_url,
-    tools::{
-        tool_completion_request, AnswerArgs, AssistantTool, DrawImageArgs, DrawImageResult,
-        WriteCodeArgs, WriteCodeResult,
+    assets::audio_key,
+    channel::EventChannel,
+    config::AppConfig,
+    conversation::{
+        assistant_message, estimate_text_tokens, estimate_tokens, tool_message, ConversationKey,
     },
+    error::{AppError, AssistantError},
+    extractors::{AppContext, AssistantInput, Locale},
+    ledger::Ledger,
+    limits::{Meter, UsageKey},
+    llm::LlmProvider,
+    metrics::Metrics,
+    sentence::{split_sentences, SentenceSplitter},
+    storage::ChatTurn,
+    tools::{tool_completion_request, ToolContext, WriteCodeResult},
     AppState,
 };
 
 use super::{
-    AssistantEvent, AssistantStep, ChatInputEvent, ChatInputSkeletonEvent, ChatReplyEvent,
-    ChatReplySkeletonEvent, SignalEvent, SpeechResult,
+    AssistantEvent, AssistantStep, ChatInputEvent, ChatInputSkeletonEvent, ChatReplyData,
+    ChatReplyDeltaEvent, ChatReplyEvent, ChatReplySkeletonEvent, SignalEvent, SpeechResult,
+    SpeechSegmentEvent, UsageResult,
 };
 
+/// max number of sentences synthesized at the same time
+const MAX_CONCURRENT_SPEECH: usize = 3;
+/// max number of times the model could be asked in a turn
+const MAX_TOOL_ROUNDS: usize = 5;
+/// rough bitrate of browser recordings (128kbps), to estimate the length of
+/// a recording when the client doesn't tell it
+const AUDIO_BYTES_PER_SECOND: f64 = 16_000.0;
+
 pub async fn assistant_handler(
     context: AppContext,
+    locale: Locale,
     State(state): State<Arc<AppState>>,
-    data: Multipart,
-) -> Result<impl IntoResponse, AppError> {
-    let device_id = &context.device_id;
-    let event_sender = state
-        .events
-        .get(device_id)
-        .ok_or_else(|| anyhow!("device_id not found for signal sender"))?
-        .clone();
-    let llm = &state.llm;
-    match process(&event_sender, device_id, llm, data).await {
-        Ok(_) => Ok(Json(json!({"status": "done"}))),
-        Err(e) => {
-            event_sender.send(error(e.to_string()))?;
-            Ok(Json(json!({"status": "error"})))
+    data: AssistantInput,
+) -> Result<Response, AppError> {
+    // held until the pipeline finishes, so shutdown could wait for it
+    let Some(_in_flight) = state.in_flight.enter() else {
+        let body = Json(json!({"status": "shutting_down"}));
+        return Ok((StatusCode::SERVICE_UNAVAILABLE, body).into_response());
+    };
+    // the page shows what went wrong, the response tells why with its status
+    run_assistant(&context, locale, &state, data).await?;
+    Ok(Json(json!({"status": "done"})).into_response())
+}
+
+/// Run a turn of the device, what went wrong is also sent to it as an error
+/// signal.
+pub(crate) async fn run_assistant(
+    context: &AppContext,
+    locale: Locale,
+    state: &AppState,
+    data: AssistantInput,
+) -> Result<(), AppError> {
+    let event_sender = state.event_channel(context.device_id);
+    process(&event_sender, context, state, data)
+        .await
+        .map_err(|e| {
+            let e = AppError::from(e);
+            event_sender.send(error(&e, locale));
+            e
+        })
+}
+
+enum UserInput {
+    /// the recording and its length in seconds, if the client tells it
+    Audio(Bytes, Option<f64>),
+    Text(String),
+}
+
+#[instrument(skip_all)]
+async fn read_input(
+    event_sender: &EventChannel,
+    metrics: &Metrics,
+    data: AssistantInput,
+) -> anyhow::Result<UserInput> {
+    let input = match data {
+        AssistantInput::Text(text) => UserInput::Text(text),
+        AssistantInput::Audio(data, duration) => UserInput::Audio(data, duration),
+        AssistantInput::Multipart(mut data) => {
+            event_sender.send(in_audio_upload());
+            let _timer = metrics.step(AssistantStep::UploadAudio);
+            let mut duration = None;
+            loop {
+                let Some(field) = data.next_field().await? else {
+                    return Err(bad_input("expected an audio or text field"));
+                };
+                match field.name() {
+                    Some("duration") => duration = field.text().await?.trim().parse().ok(),
+                    Some("audio") => break UserInput::Audio(field.bytes().await?, duration),
+                    Some("text") => break UserInput::Text(field.text().await?),
+                    _ => return Err(bad_input("expected an audio or text field")),
+                }
+            }
         }
+    };
+
+    match &input {
+        UserInput::Text(text) if text.trim().is_empty() => {
+            Err(bad_input("text input shall not be empty"))
+        }
+        // nothing recorded is the same as no recording at all
+        UserInput::Audio(data, _) if data.is_empty() => {
+            Err(bad_input("expected an audio or text field"))
+        }
+        _ => Ok(input),
     }
 }
 
-async fn transcript(llm: &LlmSdk, data: &[u8]) -> anyhow::Result<String> {
+#[instrument(skip_all, fields(bytes = data.len()))]
+async fn transcript(
+    llm: &dyn LlmProvider,
+    prompt: &str,
+    data: &[u8],
+    duration: Option<f64>,
+    meter: &Meter<'_>,
+) -> anyhow::Result<String> {
     let req = WhisperRequestBuilder::default()
         .file(data.into())
-        .prompt("If audio language is Chinese, please use Simplified Chinese")
+        .prompt(prompt)
         .request_type(WhisperRequestType::Transcription)
         .build()
         .unwrap();
     let res = llm.whisper(req).await?;
+    let seconds = duration.unwrap_or_else(|| data.len() as f64 / AUDIO_BYTES_PER_SECOND);
+    meter.transcription(seconds);
     Ok(res.text)
 }
 
 async fn chat_completion_with_tools(
-    llm: &LlmSdk,
-    prompt: &str,
+    llm: &dyn LlmProvider,
+    config: &AppConfig,
+    messages: Vec<ChatCompletionMessage>,
+    tools: Vec<Tool>,
+    meter: &Meter<'_>,
 ) -> anyhow::Result<ChatCompletionChoice> {
-    let req = tool_completion_request(prompt, "");
+    let req = tool_completion_request(&config.assistant, messages, tools)?;
     let mut res = llm.chat_completion(req).await?;
+    meter.chat(res.usage.prompt_tokens, res.usage.completion_tokens);
 
     let chiose = res
         .choices
@@ -83,179 +173,311 @@ async fn chat_completion_with_tools(
     Ok(chiose)
 }
 
-async fn write_code(llm: &LlmSdk, args: WriteCodeArgs) -> anyhow::Result<WriteCodeResult> {
-    let messages = vec![
-        ChatCompletionMessage::new_system(
-            "I'm an expert on coding, I'll write code for you in markdown format based on your prompt",
-            "Ava",
-        ),
-        ChatCompletionMessage::new_user(args.prompt, ""),
-    ];
-
-    let md = chat_completion(llm, messages).await?;
-    let content = markdown_to_html(&md, &comrak::ComrakOptions::default());
-    Ok(WriteCodeResult::new(content))
-}
-
-async fn answer(llm: &LlmSdk, args: AnswerArgs) -> anyhow::Result<String> {
-    let messages = vec![
-        ChatCompletionMessage::new_system("I can help answer anything you'd like to chat", "Ava"),
-        ChatCompletionMessage::new_user(args.prompt, ""),
-    ];
-
-    Ok(chat_completion(llm, messages).await?)
-}
-
-async fn chat_completion(
-    llm: &LlmSdk,
+/// Stream the completion to reply `id` as it's generated, returns the full content.
+/// Completed sentences are also sent to `sentences` if given.
+#[instrument(skip_all, fields(reply_id = id))]
+pub(crate) async fn chat_completion(
+    llm: &dyn LlmProvider,
+    model: ChatCompleteModel,
     messages: Vec<ChatCompletionMessage>,
+    event_sender: &EventChannel,
+    id: &str,
+    sentences: Option<mpsc::UnboundedSender<String>>,
+    meter: &Meter<'_>,
 ) -> anyhow::Result<String> {
-    let req = ChatCompletionRequest::new(messages);
-
-    let mut res = llm.chat_completion(req).await?;
+    // streamed completions carry no usage, estimate it instead
+    let prompt_tokens = messages.iter().map(estimate_tokens).sum();
+    let req = ChatCompletionRequestBuilder::default()
+        .model(model)
+        .messages(messages)
+        .build()?;
+
+    let mut stream = llm.chat_completion_stream(req).await?;
+    let mut content = String::new();
+    let mut splitter = SentenceSplitter::default();
+    while let Some(delta) = stream.next().await {
+        let delta = delta?;
+        content.push_str(&delta);
+        if let Some(tx) = &sentences {
+            // the receiver only goes away when speech failed, which fails the turn anyway
+            for sentence in splitter.push(&delta) {
+                let _ = tx.unbounded_send(sentence);
+            }
+        }
+        event_sender.send(ChatReplyDeltaEvent::new(id, delta).into());
+    }
+    if let (Some(tx), Some(sentence)) = (&sentences, splitter.finish()) {
+        let _ = tx.unbounded_send(sentence);
+    }
 
-    let content = res
-        .choices
-        .pop()
-        .ok_or_else(|| anyhow!("expect at least the choice"))?
-        .message
-        .content
-        .ok_or_else(|| anyhow!("expect content but no content available"))?;
+    meter.chat(prompt_tokens, estimate_text_tokens(&content));
 
+    if content.is_empty() {
+        bail!("expect content but no content available");
+    }
     Ok(content)
 }
 
-async fn speech(llm: &LlmSdk, device_id: &str, text: &str) -> anyhow::Result<SpeechResult> {
-    let req = SpeechRequest::new(text);
-    let data = llm.speech(req).await?;
-    let uuid = Uuid::new_v4().to_string();
-    let path = audio_path(device_id, &uuid);
-    if let Some(parent) = path.parent() {
-        // 父级路径没有创建就创建它
-        if !parent.exists() {
-            fs::create_dir_all(parent).await?
-        }
+/// Synthesize sentences as they come in, a few at a time, and push each audio
+/// segment to the reply of `ctx` in order. Returns the urls of all segments.
+pub(crate) async fn speak(
+    ctx: &ToolContext<'_>,
+    sentences: impl Stream<Item = String>,
+) -> anyhow::Result<Vec<String>> {
+    let mut segments = pin!(sentences
+        .map(|text| async move { speech(ctx, &text).await })
+        .buffered(MAX_CONCURRENT_SPEECH));
+    let mut urls = vec![];
+    while let Some(url) = segments.next().await {
+        let url = url?;
+        ctx.event_sender
+            .send(SpeechSegmentEvent::new(ctx.reply_id, &url).into());
+        urls.push(url);
     }
-    fs::write(&path, data).await?;
-    Ok(SpeechResult::new(text, audio_url(device_id, &uuid)))
+    Ok(urls)
 }
 
-async fn draw_image(
-    llm: &LlmSdk,
-    device_id: &str,
-    args: DrawImageArgs,
-) -> anyhow::Result<DrawImageResult> {
-    let req = CreateImageRequestBuilder::default()
-        .prompt(args.prompt)
-        .response_format(ImageResponseFormat::B64Json)
-        .build()
-        .unwrap();
-
-    let mut ret = llm.create_image(req).await?;
-    let img = ret
-        .data
-        .pop()
-        .ok_or_else(|| anyhow!("expect at least one data"))?;
-    let data = STANDARD.decode(img.b64_json.unwrap())?;
+#[instrument(skip_all, fields(chars = text.chars().count()))]
+async fn speech(ctx: &ToolContext<'_>, text: &str) -> anyhow::Result<String> {
+    let _timer = ctx.metrics.step(AssistantStep::Speech);
+    let assistant = &ctx.config.assistant;
+    let req = SpeechRequestBuilder::default()
+        .input(text)
+        .model(assistant.speech_model)
+        .voice(assistant.speech_voice)
+        .build()?;
+    let data = ctx.llm.speech.speech(req).await?;
+    ctx.meter.speech(text.chars().count());
     let uuid = Uuid::new_v4().to_string();
-    let path = image_path(device_id, &uuid);
-    if let Some(parent) = path.parent() {
-        // 父级路径没有创建就创建它
-        if !parent.exists() {
-            fs::create_dir_all(parent).await?
+    let key = audio_key(ctx.device_id, &uuid);
+    ctx.metrics.asset_written("audio", data.len());
+    ctx.blobs
+        .put(&key, data, "audio/mpeg")
+        .await
+        .map_err(AssistantError::Storage)?;
+    Ok(ctx.blobs.url(&key))
+}
+
+/// Ids of the reply blocks of a turn. The first one is the turn id whose
+/// skeleton is already on the page, each following one gets a new skeleton.
+struct ReplyIds {
+    id: String,
+    count: usize,
+}
+
+impl ReplyIds {
+    fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            count: 0,
         }
     }
-    fs::write(&path, data).await?;
-    Ok(DrawImageResult::new(
-        image_url(device_id, &uuid),
-        img.revised_prompt,
-    ))
+
+    fn next(&mut self, event_sender: &EventChannel) -> String {
+        let id = if self.count == 0 {
+            self.id.clone()
+        } else {
+            let id = format!("{}-{}", self.id, self.count);
+            event_sender.send(ChatReplySkeletonEvent::new(&id).into());
+            id
+        };
+        self.count += 1;
+        id
+    }
 }
 
 async fn process(
-    event_sender: &broadcast::Sender<AssistantEvent>,
-    device_id: &str,
-    llm: &LlmSdk,
-    mut data: Multipart,
+    event_sender: &EventChannel,
+    context: &AppContext,
+    state: &AppState,
+    data: AssistantInput,
 ) -> anyhow::Result<()> {
+    let key = UsageKey::from(context);
+    state.limits.admit(&key)?;
+    let ledger = Ledger::default();
+    let meter = state.limits.meter(key, &ledger);
     let id = Uuid::new_v4().to_string();
-    event_sender.send(in_audio_upload()).unwrap();
-    let Some(field) = data.next_field().await? else {
-        return Err(anyhow!("expected an audio field"))?;
-    };
+    let span = info_span!("turn", turn_id = %id, device_id = %context.device_id);
+    let ret = run_turn(event_sender, &id, context, state, data, &meter)
+        .instrument(span)
+        .await;
+    // failed turns are billed all the same
+    if let Err(e) = state.usage.add(&ledger.records()) {
+        warn!("failed to record usage: {}", e);
+    }
+    ret
+}
 
-    let data = match field.name() {
-        Some(name) if name == "audio" => field.bytes().await?,
-        _ => return Err(anyhow!("expected an audio field"))?,
+async fn run_turn(
+    event_sender: &EventChannel,
+    id: &str,
+    context: &AppContext,
+    state: &AppState,
+    data: AssistantInput,
+    meter: &Meter<'_>,
+) -> anyhow::Result<()> {
+    let device_id = context.device_id;
+    let conversation = ConversationKey::from(context);
+    let llm = &state.llm;
+    let text = match read_input(event_sender, &state.metrics, data).await? {
+        UserInput::Audio(data, duration) => {
+            event_sender.send(in_transcrition());
+            event_sender.send(ChatInputSkeletonEvent::new(id).into());
+            let _timer = state.metrics.step(AssistantStep::Transcrition);
+            transcript(
+                llm.transcription.as_ref(),
+                &state.config.assistant.transcription_prompt,
+                &data,
+                duration,
+                meter,
+            )
+            .await?
+        }
+        UserInput::Text(text) => {
+            event_sender.send(ChatInputSkeletonEvent::new(id).into());
+            text
+        }
     };
-
-    event_sender.send(in_transcrition())?;
-    event_sender.send(ChatInputSkeletonEvent::new(&id).into())?;
-
-    let text = transcript(llm, &data).await?;
-    event_sender.send(ChatInputEvent::new(&id, &text).into())?;
-
-    event_sender.send(in_thinking())?;
-    event_sender.send(ChatReplySkeletonEvent::new(&id).into())?;
-
-    let chioce = chat_completion_with_tools(llm, &text).await?;
-    match chioce.finish_reason {
-        llm_sdk::chat_completion::FinishReason::Stop => {
-            let output = chioce
-                .message
-                .content
-                .ok_or_else(|| anyhow!("expect content but no content available"))?;
-            event_sender.send(in_speech())?;
-            let ret = SpeechResult::new_text_only(&output);
-            event_sender.send(ChatReplyEvent::new(&id, ret).into())?;
-
-            let ret = speech(llm, &device_id, &output).await?;
-            event_sender.send(complete())?;
-            event_sender.send(ChatReplyEvent::new(id, ret).into())?;
+    event_sender.send(ChatInputEvent::new(id, &text).into());
+
+    event_sender.send(in_thinking());
+    event_sender.send(ChatReplySkeletonEvent::new(id).into());
+
+    let history = state
+        .conversations
+        .get(&conversation)
+        .map(|c| c.messages())
+        .unwrap_or_default();
+    // messages of this turn, only kept in the conversation once the turn succeeded
+    let mut turn = vec![ChatCompletionMessage::new_user(&text, "")];
+    // final replies of this turn, persisted into history
+    let mut replies: Vec<ChatReplyData> = vec![];
+    let mut reply_ids = ReplyIds::new(id);
+
+    // keep running the tools the model asks for until it's done
+    let mut round = 0;
+    loop {
+        if round == MAX_TOOL_ROUNDS {
+            bail!("too many tool calls in one turn");
         }
-
-        llm_sdk::chat_completion::FinishReason::ToolCalls => {
-            let tool_call = &chioce.message.tool_calls[0].function;
-            match AssistantTool::from_str(&tool_call.name) {
-                Ok(v) if v == AssistantTool::DrawImage => {
-                    let args: DrawImageArgs = serde_json::from_str(&tool_call.arguments)?;
-
-                    event_sender.send(in_draw_image())?;
-                    let ret = DrawImageResult::new("", &args.prompt);
-                    event_sender.send(ChatReplyEvent::new(&id, ret).into())?;
-
-                    let ret = draw_image(llm, device_id, args).await?;
-                    event_sender.send(complete())?;
-                    event_sender.send(ChatReplyEvent::new(&id, ret).into())?;
-                }
-                Ok(v) if v == AssistantTool::WriteCode => {
-                    event_sender.send(in_write_code())?;
-                    let ret = write_code(llm, serde_json::from_str(&tool_call.arguments)?).await?;
-
-                    event_sender.send(complete())?;
-                    event_sender.send(ChatReplyEvent::new(&id, ret).into())?;
-                }
-                Ok(v) if v == AssistantTool::Answer => {
-                    event_sender.send(in_chat_completion())?;
-                    let output = answer(llm, serde_json::from_str(&tool_call.arguments)?).await?;
-                    event_sender.send(complete())?;
+        if round > 0 {
+            event_sender.send(in_thinking());
+        }
+        round += 1;
+
+        let mut messages = history.clone();
+        messages.extend(turn.iter().cloned());
+        let tools = state.tools.definitions();
+        let chioce = {
+            let _timer = state.metrics.step(AssistantStep::Thinking);
+            chat_completion_with_tools(llm.tools.as_ref(), &state.config, messages, tools, meter)
+                .instrument(info_span!("thinking", round))
+                .await?
+        };
+        match chioce.finish_reason {
+            llm_sdk::chat_completion::FinishReason::Stop => {
+                let output = chioce.message.content.unwrap_or_default();
+                turn.push(assistant_message(&output));
+                if reply_ids.count == 0 {
+                    if output.is_empty() {
+                        bail!("expect content but no content available");
+                    }
+                    let reply_id = reply_ids.next(event_sender);
+                    event_sender.send(in_speech());
                     let ret = SpeechResult::new_text_only(&output);
-                    event_sender.send(ChatReplyEvent::new(&id, ret).into())?;
-
-                    event_sender.send(in_speech())?;
-                    let ret = speech(llm, device_id, &output).await?;
-                    event_sender.send(complete())?;
-                    event_sender.send(ChatReplyEvent::new(&id, ret).into())?;
+                    event_sender.send(ChatReplyEvent::new(&reply_id, ret).into());
+
+                    let ctx = ToolContext {
+                        config: &state.config,
+                        llm,
+                        blobs: state.blobs.as_ref(),
+                        meter,
+                        metrics: &state.metrics,
+                        device_id,
+                        history: &history,
+                        event_sender,
+                        reply_id: &reply_id,
+                    };
+                    let sentences = stream::iter(split_sentences(&output));
+                    let urls = speak(&ctx, sentences)
+                        .instrument(info_span!("speak"))
+                        .await?;
+                    let ret = SpeechResult::new(&output, urls);
+                    event_sender.send(complete());
+                    replies.push(ret.clone().into());
+                    event_sender.send(ChatReplyEvent::new(&reply_id, ret).into());
+                } else if output.trim().is_empty() {
+                    event_sender.send(complete());
+                } else {
+                    // the tools already replied, the final words are just shown as text
+                    let reply_id = reply_ids.next(event_sender);
+                    let ret = WriteCodeResult::new(markdown_to_html(
+                        &output,
+                        &comrak::ComrakOptions::default(),
+                    ));
+                    event_sender.send(complete());
+                    replies.push(ret.clone().into());
+                    event_sender.send(ChatReplyEvent::new(&reply_id, ret).into());
                 }
-                _ => {
-                    bail!("no proper tool found")
+                break;
+            }
+
+            llm_sdk::chat_completion::FinishReason::ToolCalls => {
+                turn.push(ChatCompletionMessage::Assistant(chioce.message.clone()));
+                for tool_call in &chioce.message.tool_calls {
+                    let function = &tool_call.function;
+                    // the model is told what's wrong, to call it right next round
+                    if let Err(e) = state.tools.check(&function.name, &function.arguments) {
+                        warn!("invalid tool call: {}", e);
+                        turn.push(tool_message(&tool_call.id, e));
+                        continue;
+                    }
+                    let reply_id = reply_ids.next(event_sender);
+                    let meter = meter.for_tool(&function.name);
+                    let ctx = ToolContext {
+                        config: &state.config,
+                        llm,
+                        blobs: state.blobs.as_ref(),
+                        meter: &meter,
+                        metrics: &state.metrics,
+                        device_id,
+                        history: &history,
+                        event_sender,
+                        reply_id: &reply_id,
+                    };
+                    let output = state
+                        .tools
+                        .call(&ctx, &function.name, &function.arguments)
+                        .instrument(info_span!("tool", tool = %function.name, turn_id = %id))
+                        .await?;
+                    event_sender.send(complete());
+                    replies.push(output.reply.clone());
+                    event_sender.send(ChatReplyEvent::new(&reply_id, output.reply).into());
+                    turn.push(tool_message(&tool_call.id, output.result));
                 }
             }
+            _ => {
+                bail!("stop reason not supported")
+            }
         }
-        _ => {
-            bail!("stop reason not supported")
-        }
     }
+
+    if state.config.assistant.show_usage {
+        let reply_id = reply_ids.next(event_sender);
+        let ret = UsageResult::new(meter.records());
+        replies.push(ret.clone().into());
+        event_sender.send(ChatReplyEvent::new(&reply_id, ret).into());
+    }
+
+    state
+        .conversations
+        .entry(conversation)
+        .or_default()
+        .extend(turn);
+    state
+        .history
+        .save_turn(&ChatTurn::new(id, device_id, text, replies))
+        .map_err(AssistantError::Storage)?;
     Ok(())
 }
 
@@ -271,7 +493,7 @@ fn in_thinking() -> AssistantEvent {
     SignalEvent::Processing(AssistantStep::Thinking).into()
 }
 
-fn in_chat_completion() -> AssistantEvent {
+pub(crate) fn in_chat_completion() -> AssistantEvent {
     SignalEvent::Processing(AssistantStep::ChatCompletion).into()
 }
 
@@ -279,16 +501,20 @@ fn in_speech() -> AssistantEvent {
     SignalEvent::Processing(AssistantStep::Speech).into()
 }
 
-fn in_draw_image() -> AssistantEvent {
+pub(crate) fn in_draw_image() -> AssistantEvent {
     SignalEvent::Processing(AssistantStep::DrawImage).into()
 }
 
-fn in_write_code() -> AssistantEvent {
+pub(crate) fn in_write_code() -> AssistantEvent {
     SignalEvent::Processing(AssistantStep::WriteCode).into()
 }
 
-fn error(msg: impl Into<String>) -> AssistantEvent {
-    SignalEvent::Error(msg.into()).into()
+pub(crate) fn error(e: &AppError, locale: Locale) -> AssistantEvent {
+    SignalEvent::Error(e.signal(locale)).into()
+}
+
+pub(crate) fn bad_input(msg: &str) -> anyhow::Error {
+    AssistantError::BadInput(msg.to_string()).into()
 }
 
 fn complete() -> AssistantEvent {
@@ -301,7 +527,16 @@ mod tests {
 
     #[test]
     fn test_error_render() {
-        let event: String = error("error").into();
-        assert_eq!(event, "\n<p class=\"text-red-800\">Error: error</p>\n")
+        let e = AppError::from(bad_input("text input shall not be empty"));
+        let event: String = error(&e, Locale::Zh).into();
+        assert_eq!(
+            event,
+            "\n<p class=\"text-red-800\" data-error=\"bad_input\"><i class=\"fa-solid fa-circle-exclamation\"></i>抱歉，无法理解这个请求。 <span class=\"text-red-600\">(text input shall not be empty)</span></p>\n"
+        );
+
+        let e = AppError::from(anyhow!("disk is on fire"));
+        let event: String = error(&e, Locale::En).into();
+        assert!(event.contains("data-error=\"internal\""));
+        assert!(!event.contains("disk is on fire"));
     }
 }
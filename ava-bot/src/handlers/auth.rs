@@ -0,0 +1,259 @@
+use std::sync::Arc;
+
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+    Form,
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use serde::Deserialize;
+use tokio::task;
+
+use crate::{
+    auth::{
+        dummy_password_hash, hash_password, verify_password, Account, OidcLogin,
+        COOKIE_NAME_SESSION,
+    },
+    error::AppError,
+    extractors::ClientInfo,
+    AppState, DeviceId, COOKIE_NAME_DEVICE_ID,
+};
+
+/// cookie carrying the secrets of an oidc sign in, checked by the callback
+const COOKIE_NAME_OIDC_STATE: &str = "oidc_state";
+const OIDC_COOKIE_PATH: &str = "/auth/oidc";
+const MAX_USERNAME_LEN: usize = 32;
+const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Debug, Template)]
+#[template(path = "login.html.j2")]
+struct LoginTemplate {
+    error: Option<String>,
+    required: bool,
+    local_login: bool,
+    allow_signup: bool,
+    // name of the oidc provider, if configured
+    oidc: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// Query of the redirect back from the oidc provider.
+#[derive(Debug, Deserialize)]
+pub struct OidcCallback {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+}
+
+pub async fn login_page(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    LoginTemplate::new(&state, None)
+}
+
+pub async fn login_handler(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Form(credentials): Form<Credentials>,
+) -> Result<Response, AppError> {
+    if !state.config.auth.local_login {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let account = state
+        .accounts
+        .find_by_username(&credentials.username)?
+        .filter(|v| v.password_hash.is_some());
+    let hash = account.as_ref().and_then(|v| v.password_hash.clone());
+    let password = credentials.password;
+    // unknown users are checked all the same, so it takes as long as a wrong
+    // password and doesn't tell which usernames exist
+    let verified = task::spawn_blocking(move || {
+        let hash = hash.unwrap_or_else(|| dummy_password_hash().to_string());
+        verify_password(&password, &hash)
+    })
+    .await?;
+    let (Some(account), true) = (account, verified) else {
+        return Ok(login_error(
+            &state,
+            StatusCode::UNAUTHORIZED,
+            "invalid username or password",
+        ));
+    };
+    sign_in(&state, &client, jar, &account)
+}
+
+pub async fn signup_handler(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Form(credentials): Form<Credentials>,
+) -> Result<Response, AppError> {
+    let config = &state.config.auth;
+    if !config.local_login || !config.allow_signup {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let Credentials { username, password } = credentials;
+    if let Err(msg) = check_credentials(&username, &password) {
+        return Ok(login_error(&state, StatusCode::BAD_REQUEST, msg));
+    }
+    if state.accounts.find_by_username(&username)?.is_some() {
+        return Ok(login_error(
+            &state,
+            StatusCode::CONFLICT,
+            "username is taken",
+        ));
+    }
+    let hash = task::spawn_blocking(move || hash_password(&password)).await??;
+    let mut account = Account::new(username);
+    account.password_hash = Some(hash);
+    state.accounts.create_account(&account)?;
+    sign_in(&state, &client, jar, &account)
+}
+
+/// End the session. The device stays with the account, the index page gives
+/// the browser a new one.
+pub async fn logout_handler(
+    State(state): State<Arc<AppState>>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(cookie) = jar.get(COOKIE_NAME_SESSION) {
+        state.accounts.delete_session(cookie.value())?;
+    }
+    let jar = jar.remove(Cookie::build(COOKIE_NAME_SESSION, "").path("/").finish());
+    Ok((jar, Redirect::to("/")))
+}
+
+pub async fn oidc_login_handler(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let Some(oidc) = &state.oidc else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let login = OidcLogin::new();
+    let url = oidc.authorize_url(&login).await?;
+    let cookie = Cookie::build(COOKIE_NAME_OIDC_STATE, login.to_cookie())
+        .path(OIDC_COOKIE_PATH)
+        .http_only(true)
+        .secure(client.is_https())
+        .same_site(SameSite::Lax)
+        .finish();
+    Ok((jar.add(cookie), Redirect::to(&url)).into_response())
+}
+
+pub async fn oidc_callback_handler(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    jar: CookieJar,
+    Query(params): Query<OidcCallback>,
+) -> Result<Response, AppError> {
+    let Some(oidc) = &state.oidc else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if let Some(error) = params.error {
+        let msg = format!("sign in with {} failed: {}", oidc.name(), error);
+        return Ok(login_error(&state, StatusCode::UNAUTHORIZED, msg));
+    }
+    let login = jar
+        .get(COOKIE_NAME_OIDC_STATE)
+        .and_then(|v| OidcLogin::from_cookie(v.value()));
+    let (Some(code), Some(csrf)) = (params.code, params.state) else {
+        return Ok(StatusCode::BAD_REQUEST.into_response());
+    };
+    let Some(login) = login.filter(|v| v.state == csrf) else {
+        let msg = "sign in expired, please try again";
+        return Ok(login_error(&state, StatusCode::BAD_REQUEST, msg));
+    };
+    let jar = jar.remove(
+        Cookie::build(COOKIE_NAME_OIDC_STATE, "")
+            .path(OIDC_COOKIE_PATH)
+            .finish(),
+    );
+
+    let info = oidc.user_info(&code, &login).await?;
+    let account = match state.accounts.find_by_oidc_subject(&info.sub)? {
+        Some(account) => account,
+        None => {
+            let name = info.username();
+            let mut account = Account::new(name);
+            // someone else has the name, keep the accounts apart
+            if state.accounts.find_by_username(name)?.is_some() {
+                account.username = format!("{}-{}", name, &account.id.simple().to_string()[..8]);
+            }
+            account.oidc_subject = Some(info.sub.clone());
+            state.accounts.create_account(&account)?;
+            account
+        }
+    };
+    sign_in(&state, &client, jar, &account)
+}
+
+impl LoginTemplate {
+    fn new(state: &AppState, error: Option<String>) -> Self {
+        let config = &state.config.auth;
+        Self {
+            error,
+            required: config.required,
+            local_login: config.local_login,
+            allow_signup: config.allow_signup,
+            oidc: state.oidc.as_ref().map(|v| v.name().to_string()),
+        }
+    }
+}
+
+/// Start a session and go back to the index page. The device of the browser
+/// is linked to the account, unless it belongs to another one, then the index
+/// page gives the browser a new one.
+fn sign_in(
+    state: &AppState,
+    client: &ClientInfo,
+    jar: CookieJar,
+    account: &Account,
+) -> Result<Response, AppError> {
+    let device_id = jar
+        .get(COOKIE_NAME_DEVICE_ID)
+        .and_then(|v| v.value().parse::<DeviceId>().ok());
+    if let Some(device_id) = device_id {
+        if state.accounts.device_account(device_id)?.is_none() {
+            state.accounts.link_device(account.id, device_id)?;
+        }
+    }
+    let token = state.start_session(account)?;
+    let cookie = Cookie::build(COOKIE_NAME_SESSION, token)
+        .path("/")
+        .http_only(true)
+        .secure(client.is_https())
+        .same_site(SameSite::Lax)
+        .permanent()
+        .finish();
+    Ok((jar.add(cookie), Redirect::to("/")).into_response())
+}
+
+fn login_error(state: &AppState, status: StatusCode, msg: impl Into<String>) -> Response {
+    (status, LoginTemplate::new(state, Some(msg.into()))).into_response()
+}
+
+fn check_credentials(username: &str, password: &str) -> Result<(), &'static str> {
+    let valid_name = !username.is_empty()
+        && username.chars().count() <= MAX_USERNAME_LEN
+        && username
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.@".contains(c));
+    if !valid_name {
+        return Err("username shall be 1 to 32 letters, digits or -_.@");
+    }
+    if password.chars().count() < MIN_PASSWORD_LEN {
+        return Err("password shall be at least 8 characters");
+    }
+    Ok(())
+}
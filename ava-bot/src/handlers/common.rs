@@ -1,26 +1,109 @@
+use std::sync::Arc;
+
 use askama::Template;
-use axum::response::IntoResponse;
-use axum_extra::extract::{cookie::Cookie, CookieJar};
-use uuid::Uuid;
+use axum::{
+    extract::State,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    CookieJar,
+};
+use tracing::warn;
+
+use crate::{
+    auth::Auth, error::AppError, extractors::ClientInfo, AppState, DeviceId, COOKIE_NAME_DEVICE_ID,
+};
+
+use super::{ChatInputHistory, ChatReplyHistory};
 
-use crate::COOKIE_NAME_DEVICE_ID;
+/// max number of past turns rendered on the index page
+const MAX_HISTORY_TURNS: usize = 50;
 
 #[derive(Debug, Template)]
 #[template(path = "index.html.j2")]
-struct IndexTemplate {}
-
-pub async fn index_page(jar: CookieJar) -> impl IntoResponse {
-    let jar = match jar.get(COOKIE_NAME_DEVICE_ID) {
-        Some(_) => jar,
-        None => {
-            let device_id = Uuid::new_v4().to_string();
-            let cookie = Cookie::build(COOKIE_NAME_DEVICE_ID, device_id)
-                .path("/")
-                .secure(true)
-                .permanent()
-                .finish();
-            jar.add(cookie)
+struct IndexTemplate {
+    // rendered chat items of past turns
+    history: Vec<String>,
+    // name of the signed in user
+    username: Option<String>,
+}
+
+pub async fn index_page(
+    State(state): State<Arc<AppState>>,
+    client: ClientInfo,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let current = jar
+        .get(COOKIE_NAME_DEVICE_ID)
+        .and_then(|cookie| cookie.value().parse::<DeviceId>().ok());
+    let mut auth = match current {
+        Some(device_id) => state.authenticate(&jar, device_id)?,
+        None => Auth::Denied,
+    };
+    // a missing or tampered cookie, or a device of an account which isn't
+    // signed in, gets a new device
+    let (jar, devices) = match (current, &auth) {
+        (Some(device_id), Auth::Anonymous) => (jar, vec![device_id]),
+        (Some(_), Auth::User(user)) => (jar, user.devices.clone()),
+        _ => {
+            let device_id = DeviceId::new();
+            // the new device of a signed in user is linked right away
+            auth = match state.session_account(&jar)? {
+                Some(mut account) => {
+                    state.accounts.link_device(account.id, device_id)?;
+                    account.devices.push(device_id);
+                    Auth::User(account.into())
+                }
+                None => Auth::Anonymous,
+            };
+            let devices = match &auth {
+                Auth::User(user) => user.devices.clone(),
+                _ => vec![],
+            };
+            (jar.add(device_cookie(device_id, &client)), devices)
         }
     };
-    (jar, IndexTemplate {})
+    let username = match auth {
+        Auth::User(user) => Some(user.username),
+        _ if state.config.auth.required => return Ok((jar, Redirect::to("/login")).into_response()),
+        _ => None,
+    };
+    let history = load_history(&state, &devices);
+    Ok((jar, IndexTemplate { history, username }).into_response())
+}
+
+/// The device is the identity of an anonymous user, whoever holds the cookie
+/// gets its history and assets.
+pub(crate) fn device_cookie(device_id: DeviceId, client: &ClientInfo) -> Cookie<'static> {
+    Cookie::build(COOKIE_NAME_DEVICE_ID, device_id.to_string())
+        .path("/")
+        .http_only(true)
+        .secure(client.is_https())
+        .same_site(SameSite::Lax)
+        .permanent()
+        .finish()
+}
+
+/// latest turns of all the devices, a signed in user sees the turns of every
+/// device linked to the account
+fn load_history(state: &AppState, devices: &[DeviceId]) -> Vec<String> {
+    let mut turns = vec![];
+    for device_id in devices {
+        match state.history.list_turns(*device_id, MAX_HISTORY_TURNS) {
+            Ok(v) => turns.extend(v),
+            Err(e) => warn!("failed to load history for {}: {}", device_id, e),
+        }
+    }
+    turns.sort_by_key(|turn| turn.created_at);
+    let turns = &turns[turns.len().saturating_sub(MAX_HISTORY_TURNS)..];
+    turns
+        .iter()
+        .flat_map(|turn| {
+            [
+                ChatInputHistory::new(turn).into(),
+                ChatReplyHistory::new(turn).into(),
+            ]
+        })
+        .collect()
 }
@@ -2,56 +2,72 @@ use std::{convert::Infallible, sync::Arc, time::Duration};
 
 use axum::{
     extract::State,
+    http::HeaderMap,
     response::{
         sse::{Event, KeepAlive},
         IntoResponse, Sse,
     },
 };
-use dashmap::DashMap;
-use tokio::sync::broadcast;
-use tokio_stream::{wrappers::BroadcastStream, StreamExt as _};
+use futures::{future, stream, StreamExt};
+use serde_json::json;
+use tokio_stream::wrappers::BroadcastStream;
 use tracing::info;
 
-use crate::{extractors::AppContext, AppState};
-
-use super::AssistantEvent;
+use crate::{
+    channel::{EventChannel, Subscription},
+    extractors::{AppContext, ClientInfo},
+    AppState,
+};
 
-const MAX_EVENTS: usize = 128;
+use super::{AssistantEvent, SignalEvent};
 
 pub async fn events_handler(
     context: AppContext,
+    client: ClientInfo,
+    headers: HeaderMap,
     State(state): State<Arc<AppState>>,
 ) -> impl IntoResponse {
-    info!("user connected for chats");
-    sse_handler(context, &state.events).await
+    match client.ip {
+        Some(ip) => info!("user connected for chats from {}", ip),
+        None => info!("user connected for chats"),
+    }
+    // sent by the browser when it reconnects, it's the id of the last event it got
+    let last_id = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    sse_handler(&state.event_channel(context.device_id), last_id)
 }
 
-pub async fn sse_handler(
-    context: AppContext,
-    map: &DashMap<String, broadcast::Sender<AssistantEvent>>,
-) -> impl IntoResponse {
-    let device_id = &context.device_id;
-    let rx = if let Some(tx) = map.get(device_id) {
-        tx.subscribe()
-    } else {
-        let (tx, rx) = broadcast::channel(MAX_EVENTS);
-        map.insert(device_id.to_string(), tx);
-        rx
-    };
+fn sse_handler(channel: &Arc<EventChannel>, last_id: Option<u64>) -> impl IntoResponse {
+    let Subscription {
+        missed,
+        receiver,
+        subscriber,
+    } = channel.subscribe(last_id);
 
-    // wrap receiver in a stream
-    let stream = BroadcastStream::new(rx)
-        .filter_map(|v| v.ok())
-        .map(|v| {
-            let (event, id) = match &v {
-                AssistantEvent::Signal(_) => ("signal", "".to_string()),
-                AssistantEvent::InputSkeleton(_) => ("input_skeleton", "".to_string()),
-                AssistantEvent::Input(v) => ("input", v.id.to_string()),
-                AssistantEvent::ReplySkeleton(_) => ("reply_skeleton", "".to_string()),
-                AssistantEvent::Reply(v) => ("reply", v.id.to_string()),
+    // wrap receiver in a stream, after the events missed
+    let stream = stream::iter(missed)
+        .chain(BroadcastStream::new(receiver).filter_map(|v| future::ready(v.ok())))
+        // the shutdown signal is the last event, the stream ends right after it
+        .flat_map(|v| {
+            let shutdown = matches!(v.event, AssistantEvent::Signal(SignalEvent::Shutdown));
+            stream::iter([Some(v)].into_iter().chain(shutdown.then_some(None)))
+        })
+        .take_while(|v| future::ready(v.is_some()))
+        .filter_map(future::ready)
+        .map(move |v| {
+            // the client is connected as long as the stream is alive
+            let _ = &subscriber;
+            let name = v.event.name();
+            let target = v.event.target().map(str::to_string);
+            let data: String = v.event.into();
+            // the id is taken by the replay, the page finds the block by target
+            let data = match target {
+                Some(target) => json!({"target": target, "data": data}).to_string(),
+                None => data,
             };
-            let data: String = v.into();
-            Event::default().data(data).event(event).id(id)
+            Event::default().data(data).event(name).id(v.id.to_string())
         })
         .map(Ok::<_, Infallible>);
 
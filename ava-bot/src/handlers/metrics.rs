@@ -0,0 +1,14 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use prometheus::TEXT_FORMAT;
+
+use crate::{error::AppError, AppState};
+
+/// Metrics in the Prometheus text format.
+pub async fn metrics_handler(
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let body = state.metrics.render(&state.events)?;
+    Ok(([(header::CONTENT_TYPE, TEXT_FORMAT)], body))
+}
@@ -1,17 +1,34 @@
+mod admin;
+mod assets;
 mod assistant;
+mod auth;
 mod common;
 mod events;
+mod metrics;
+mod usage;
+mod ws;
 
+pub use admin::*;
 use askama::Template;
+pub use assets::*;
 pub use assistant::*;
+pub use auth::*;
 use chrono::Local;
 pub use common::*;
 use derive_more::From;
 pub use events::*;
-use serde::{Deserialize, Serialize};
+pub use metrics::*;
+use serde::{Deserialize, Deserializer, Serialize};
 use strum::{Display, EnumString};
+pub use usage::*;
+pub use ws::*;
 
-use crate::tools::{DrawImageResult, WriteCodeResult};
+use crate::{
+    error::ErrorKind,
+    ledger::UsageRecord,
+    storage::ChatTurn,
+    tools::{DrawImageResult, WriteCodeResult},
+};
 
 #[derive(Debug, Clone, From)]
 pub(crate) enum AssistantEvent {
@@ -20,6 +37,8 @@ pub(crate) enum AssistantEvent {
     Input(ChatInputEvent),
     ReplySkeleton(ChatReplySkeletonEvent),
     Reply(ChatReplyEvent),
+    ReplyDelta(ChatReplyDeltaEvent),
+    SpeechSegment(SpeechSegmentEvent),
 }
 
 #[derive(Debug, Clone, Template, Serialize, Deserialize)]
@@ -28,8 +47,19 @@ pub(crate) enum AssistantEvent {
 pub(crate) enum SignalEvent {
     Processing(AssistantStep),
     Finish(AssistantStep),
-    Error(String),
+    Error(ErrorSignal),
     Complete,
+    /// the server is shutting down, the page reconnects once it's back
+    Shutdown,
+}
+
+/// A failed turn, told to the user in their language.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ErrorSignal {
+    pub(crate) kind: ErrorKind,
+    pub(crate) message: String,
+    /// what's wrong with the input, for bad input only
+    pub(crate) detail: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, EnumString, Display)]
@@ -45,6 +75,32 @@ pub(crate) enum AssistantStep {
     Speech,
 }
 
+impl AssistantEvent {
+    /// name of the event sent to the client
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            AssistantEvent::Signal(_) => "signal",
+            AssistantEvent::InputSkeleton(_) => "input_skeleton",
+            AssistantEvent::Input(_) => "input",
+            AssistantEvent::ReplySkeleton(_) => "reply_skeleton",
+            AssistantEvent::Reply(_) => "reply",
+            AssistantEvent::ReplyDelta(_) => "reply_delta",
+            AssistantEvent::SpeechSegment(_) => "speech_segment",
+        }
+    }
+
+    /// id of the block on the page the event updates
+    pub(crate) fn target(&self) -> Option<&str> {
+        match self {
+            AssistantEvent::Input(v) => Some(&v.id),
+            AssistantEvent::Reply(v) => Some(&v.id),
+            AssistantEvent::ReplyDelta(v) => Some(&v.id),
+            AssistantEvent::SpeechSegment(v) => Some(&v.id),
+            _ => None,
+        }
+    }
+}
+
 impl From<SignalEvent> for String {
     fn from(event: SignalEvent) -> Self {
         event.render().unwrap()
@@ -82,6 +138,41 @@ pub(crate) struct ChatReplyEvent {
     data: ChatReplyData,
 }
 
+/// A piece of the reply text while it's being generated, appended to the reply
+/// as plain text by the page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ChatReplyDeltaEvent {
+    id: String,
+    delta: String,
+}
+
+/// An audio segment of the reply, queued for playing by the page.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct SpeechSegmentEvent {
+    id: String,
+    url: String,
+}
+
+#[derive(Debug, Clone, Template)]
+#[template(path = "history/chat_input.html.j2")]
+pub(crate) struct ChatInputHistory {
+    id: String,
+    datetime: String,
+    avatar: String,
+    name: String,
+    content: String,
+}
+
+#[derive(Debug, Clone, Template)]
+#[template(path = "history/chat_reply.html.j2")]
+pub(crate) struct ChatReplyHistory {
+    id: String,
+    avatar: String,
+    name: String,
+    // rendered reply blocks
+    replies: Vec<String>,
+}
+
 impl ChatInputSkeletonEvent {
     pub fn new(id: impl Into<String>) -> Self {
         Self {
@@ -108,25 +199,65 @@ pub(crate) enum ChatReplyData {
     Speech(SpeechResult),
     Image(DrawImageResult),
     Markdown(WriteCodeResult),
+    Usage(UsageResult),
 }
 
 #[derive(Debug, Clone, Template, Serialize, Deserialize)]
 #[template(path = "blocks/speech.html.j2")]
 pub(crate) struct SpeechResult {
     text: String,
-    url: String,
+    // audio segments, played in order; turns saved before speech was
+    // segmented have a single `url`
+    #[serde(default, alias = "url", deserialize_with = "one_or_many")]
+    urls: Vec<String>,
+}
+
+/// What the LLM calls of a turn used and cost, collapsed below the replies.
+#[derive(Debug, Clone, Template, Serialize, Deserialize)]
+#[template(path = "blocks/usage.html.j2")]
+pub(crate) struct UsageResult {
+    records: Vec<UsageRecord>,
+}
+
+impl ChatReplyData {
+    /// urls of the generated files the reply refers to
+    pub(crate) fn asset_urls(&self) -> Vec<&str> {
+        match self {
+            ChatReplyData::Speech(v) => v.urls.iter().map(|v| v.as_str()).collect(),
+            ChatReplyData::Image(v) => vec![v.url.as_str()],
+            ChatReplyData::Markdown(_) | ChatReplyData::Usage(_) => vec![],
+        }
+    }
 }
 
 impl SpeechResult {
-    pub(crate) fn new(text: impl Into<String>, url: impl Into<String>) -> Self {
+    pub(crate) fn new(text: impl Into<String>, urls: Vec<String>) -> Self {
         SpeechResult {
             text: text.into(),
-            url: url.into(),
+            urls,
         }
     }
 
     pub(crate) fn new_text_only(text: impl Into<String>) -> Self {
-        Self::new(text, "".to_string())
+        Self::new(text, vec![])
+    }
+
+    fn first_url(&self) -> &str {
+        self.urls.first().map(|v| v.as_str()).unwrap_or_default()
+    }
+
+    fn segments(&self) -> String {
+        serde_json::to_string(&self.urls).unwrap()
+    }
+}
+
+impl UsageResult {
+    pub(crate) fn new(records: Vec<UsageRecord>) -> Self {
+        Self { records }
+    }
+
+    fn total_cost(&self) -> f64 {
+        self.records.iter().map(|v| v.cost).sum()
     }
 }
 
@@ -149,12 +280,63 @@ impl ChatReplyEvent {
     }
 }
 
+impl ChatReplyDeltaEvent {
+    pub fn new(id: impl Into<String>, delta: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            delta: delta.into(),
+        }
+    }
+}
+
+impl SpeechSegmentEvent {
+    pub fn new(id: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            url: url.into(),
+        }
+    }
+}
+
+impl ChatInputHistory {
+    pub fn new(turn: &ChatTurn) -> Self {
+        Self {
+            id: turn.id.clone(),
+            datetime: turn.datetime(),
+            avatar: "https://i.pravatar.cc/300".to_string(),
+            name: "User".to_string(),
+            content: turn.input.clone(),
+        }
+    }
+}
+
+impl ChatReplyHistory {
+    pub fn new(turn: &ChatTurn) -> Self {
+        Self {
+            id: turn.id.clone(),
+            avatar: "/public/images/ava-small.png".to_string(),
+            name: "Ava".to_string(),
+            replies: turn
+                .replies
+                .iter()
+                .map(|data| ChatReplyEvent::new(&turn.id, data.clone()).into())
+                .collect(),
+        }
+    }
+}
+
 impl From<SpeechResult> for String {
     fn from(result: SpeechResult) -> Self {
         result.render().unwrap()
     }
 }
 
+impl From<UsageResult> for String {
+    fn from(result: UsageResult) -> Self {
+        result.render().unwrap()
+    }
+}
+
 impl From<ChatInputEvent> for String {
     fn from(event: ChatInputEvent) -> Self {
         event.render().unwrap()
@@ -179,6 +361,30 @@ impl From<ChatReplySkeletonEvent> for String {
     }
 }
 
+impl From<ChatReplyDeltaEvent> for String {
+    fn from(event: ChatReplyDeltaEvent) -> Self {
+        event.delta
+    }
+}
+
+impl From<SpeechSegmentEvent> for String {
+    fn from(event: SpeechSegmentEvent) -> Self {
+        event.url
+    }
+}
+
+impl From<ChatInputHistory> for String {
+    fn from(v: ChatInputHistory) -> Self {
+        v.render().unwrap()
+    }
+}
+
+impl From<ChatReplyHistory> for String {
+    fn from(v: ChatReplyHistory) -> Self {
+        v.render().unwrap()
+    }
+}
+
 impl From<AssistantEvent> for String {
     fn from(event: AssistantEvent) -> Self {
         match event {
@@ -187,6 +393,47 @@ impl From<AssistantEvent> for String {
             AssistantEvent::Input(v) => v.into(),
             AssistantEvent::ReplySkeleton(v) => v.into(),
             AssistantEvent::Reply(v) => v.into(),
+            AssistantEvent::ReplyDelta(v) => v.into(),
+            AssistantEvent::SpeechSegment(v) => v.into(),
         }
     }
 }
+
+/// A list, or a single value saved before the field became a list, empty for
+/// none.
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(v) if v.is_empty() => vec![],
+        OneOrMany::One(v) => vec![v],
+        OneOrMany::Many(v) => v,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn speech_result_should_load_single_url_of_old_turns() -> anyhow::Result<()> {
+        let v: SpeechResult = serde_json::from_value(json!({"text": "hi", "url": "/a.mp3"}))?;
+        assert_eq!(v.urls, ["/a.mp3"]);
+        let v: SpeechResult = serde_json::from_value(json!({"text": "hi", "url": ""}))?;
+        assert!(v.urls.is_empty());
+        let v: SpeechResult = serde_json::from_value(json!({"text": "hi", "urls": ["/a", "/b"]}))?;
+        assert_eq!(v.urls, ["/a", "/b"]);
+        let v: SpeechResult = serde_json::from_value(json!({"text": "hi"}))?;
+        assert!(v.urls.is_empty());
+        Ok(())
+    }
+}
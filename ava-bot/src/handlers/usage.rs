@@ -0,0 +1,15 @@
+use std::sync::Arc;
+
+use axum::{extract::State, response::IntoResponse, Json};
+
+use crate::{error::AppError, extractors::AppContext, limits::UsageKey, AppState};
+
+/// Today's spend and the recent requests of the current user, or the client
+/// address if not signed in.
+pub async fn usage_handler(
+    context: AppContext,
+    State(state): State<Arc<AppState>>,
+) -> Result<impl IntoResponse, AppError> {
+    let key = UsageKey::from(&context);
+    Ok(Json(state.limits.summary(&key)?))
+}
@@ -0,0 +1,264 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    http::{header, HeaderMap, StatusCode, Uri},
+    response::{IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use bytes::BytesMut;
+use futures::{future, stream, SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::mpsc, task::JoinSet};
+use tokio_stream::wrappers::BroadcastStream;
+use tracing::{info, warn};
+
+use crate::{
+    auth::COOKIE_NAME_SESSION,
+    channel::{SequencedEvent, Subscription},
+    error::AppError,
+    extractors::{AppContext, AssistantInput, ClientInfo, Locale},
+    AppState,
+};
+
+use super::{bad_input, error, run_assistant, AssistantEvent, SignalEvent};
+
+/// whisper takes files up to 25MB
+const MAX_AUDIO_BYTES: usize = 25 * 1024 * 1024;
+/// reason of the close frame sent when the server stops
+const SHUTDOWN_REASON: &str = "the server is shutting down";
+/// time the client has to answer the close frame
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Text frames from the client, the recording comes in binary frames.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// a typed question
+    Text { text: String },
+    /// the recording sent so far is complete, its length in seconds if known
+    AudioEnd { duration: Option<f64> },
+}
+
+/// An event pushed to the client, the same as the ones on `/events`.
+#[derive(Debug, Serialize)]
+struct EventFrame {
+    id: u64,
+    event: &'static str,
+    /// id of the block on the page the event updates
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    data: String,
+}
+
+/// Both ways of the assistant on one connection: the client streams the
+/// recording in binary frames while speaking and ends it with an `audio_end`
+/// message, the events of the device are pushed back as json. Pages of other
+/// sites are refused, so are clients with a session but no `Origin`.
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    context: AppContext,
+    locale: Locale,
+    client: ClientInfo,
+    jar: CookieJar,
+    headers: HeaderMap,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    // the cookies come along with a socket opened by any site, unlike fetch
+    if !is_same_origin(&headers, &client, &jar) {
+        warn!("refused websocket from another site");
+        return (StatusCode::FORBIDDEN, "cross-site websocket is not allowed").into_response();
+    }
+    match client.ip {
+        Some(ip) => info!("user connected over websocket from {}", ip),
+        None => info!("user connected over websocket"),
+    }
+    ws.on_upgrade(move |socket| serve_socket(socket, context, jar, locale, state))
+}
+
+/// Whether the page opening the socket is served by us. Browsers always send
+/// `Origin`, other clients may leave it out to use the assistant anonymously,
+/// but not with a session.
+fn is_same_origin(headers: &HeaderMap, client: &ClientInfo, jar: &CookieJar) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return jar.get(COOKIE_NAME_SESSION).is_none();
+    };
+    let origin = origin
+        .to_str()
+        .ok()
+        .and_then(|v| v.parse::<Uri>().ok())
+        .and_then(|v| v.authority().map(|v| v.as_str().to_ascii_lowercase()));
+    match (origin, &client.host) {
+        (Some(origin), Some(host)) => origin == host.to_ascii_lowercase(),
+        _ => false,
+    }
+}
+
+async fn serve_socket(
+    socket: WebSocket,
+    context: AppContext,
+    jar: CookieJar,
+    locale: Locale,
+    state: Arc<AppState>,
+) {
+    let (mut sink, mut frames) = socket.split();
+    let Subscription {
+        missed,
+        receiver,
+        subscriber,
+    } = state.event_channel(context.device_id).subscribe(None);
+
+    // the socket is closed with the frame sent here, and why
+    let (close_tx, mut close_rx) = mpsc::channel(1);
+    let mut push = tokio::spawn(async move {
+        // the client is connected as long as the events are pushed
+        let _subscriber = subscriber;
+        let mut events = stream::iter(missed)
+            .chain(BroadcastStream::new(receiver).filter_map(|v| future::ready(v.ok())));
+        let frame = loop {
+            let v = tokio::select! {
+                v = events.next() => v,
+                Some(frame) = close_rx.recv() => break frame,
+            };
+            let Some(v) = v else {
+                let _ = sink.close().await;
+                return;
+            };
+            let shutdown = matches!(v.event, AssistantEvent::Signal(SignalEvent::Shutdown));
+            if sink
+                .send(Message::Text(EventFrame::from(v).to_json()))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            // the shutdown signal is the last event, the socket closes after it
+            if shutdown {
+                break close_frame(close_code::AWAY, SHUTDOWN_REASON);
+            }
+        };
+        let _ = sink.send(Message::Close(Some(frame))).await;
+    });
+
+    let channel = state.event_channel(context.device_id);
+    // the turns go on even if the client leaves in the middle of them
+    let mut turns = JoinSet::new();
+    let receive = async {
+        let mut audio = BytesMut::new();
+        // the recording went over the limit, the rest of it is dropped
+        let mut rejected = false;
+        loop {
+            // frames are read while the turns run, to notice the client leaving
+            let frame = tokio::select! {
+                frame = frames.next() => frame,
+                Some(started) = turns.join_next() => {
+                    if let Ok(false) = started {
+                        let _ = close_tx.try_send(close_frame(close_code::AWAY, SHUTDOWN_REASON));
+                        return true;
+                    }
+                    continue;
+                }
+            };
+            let Some(Ok(frame)) = frame else {
+                break;
+            };
+            let input = match frame {
+                Message::Binary(_) if rejected => continue,
+                Message::Binary(data) => {
+                    if audio.len() + data.len() > MAX_AUDIO_BYTES {
+                        audio.clear();
+                        rejected = true;
+                        let e = AppError::from(bad_input("the recording is too long"));
+                        channel.send(error(&e, locale));
+                    } else {
+                        audio.extend_from_slice(&data);
+                    }
+                    continue;
+                }
+                Message::Text(text) => match serde_json::from_str(&text) {
+                    Ok(ClientMessage::Text { text }) => AssistantInput::Text(text),
+                    Ok(ClientMessage::AudioEnd { .. }) if rejected => {
+                        rejected = false;
+                        continue;
+                    }
+                    Ok(ClientMessage::AudioEnd { duration }) => {
+                        AssistantInput::Audio(audio.split().freeze(), duration)
+                    }
+                    Err(e) => {
+                        let e = AppError::from(bad_input(&format!("invalid message: {}", e)));
+                        channel.send(error(&e, locale));
+                        continue;
+                    }
+                },
+                Message::Close(_) => break,
+                _ => continue,
+            };
+            // the session may be signed out or expired since the socket opened
+            let context = match AppContext::resolve(&state, &jar, context.device_id, context.ip) {
+                Ok(context) => context,
+                Err((_, reason)) => {
+                    let _ = close_tx.try_send(close_frame(close_code::POLICY, reason));
+                    return true;
+                }
+            };
+            let state = state.clone();
+            turns.spawn(async move {
+                // held until the turn finishes, so shutdown could wait for it
+                let Some(_in_flight) = state.in_flight.enter() else {
+                    return false;
+                };
+                // the error is sent to the client as a signal already
+                if let Err(e) = run_assistant(&context, locale, &state, input).await {
+                    warn!("turn over websocket failed: {}", e.kind());
+                }
+                true
+            });
+        }
+        false
+    };
+
+    let closing = tokio::select! {
+        _ = &mut push => true,
+        closing = receive => {
+            if closing {
+                // it sends the close frame then ends
+                let _ = push.await;
+            } else {
+                push.abort();
+            }
+            closing
+        }
+    };
+    turns.detach_all();
+    if closing {
+        // kept until the client answers the close frame, so it gets the reason
+        let _ = tokio::time::timeout(CLOSE_TIMEOUT, frames.count()).await;
+    }
+}
+
+fn close_frame(code: u16, reason: &'static str) -> CloseFrame<'static> {
+    CloseFrame {
+        code,
+        reason: reason.into(),
+    }
+}
+
+impl From<SequencedEvent> for EventFrame {
+    fn from(v: SequencedEvent) -> Self {
+        Self {
+            id: v.id,
+            event: v.event.name(),
+            target: v.event.target().map(str::to_string),
+            data: v.event.into(),
+        }
+    }
+}
+
+impl EventFrame {
+    fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("event frame shall serialize")
+    }
+}
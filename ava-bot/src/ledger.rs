@@ -0,0 +1,231 @@
+use std::{collections::BTreeMap, fmt, sync::Mutex};
+
+use chrono::{NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::PricesConfig;
+
+/// tool name of the calls the assistant makes for itself, e.g. transcription
+/// and picking the tools
+pub(crate) const ASSISTANT: &str = "assistant";
+
+/// What an LLM call used, in the units it's billed by.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Usage {
+    Chat {
+        prompt_tokens: usize,
+        completion_tokens: usize,
+    },
+    Transcription {
+        seconds: f64,
+    },
+    Speech {
+        characters: usize,
+    },
+    Image {
+        count: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct UsageRecord {
+    /// the tool the call is made for
+    pub(crate) tool: String,
+    pub(crate) usage: Usage,
+    /// dollars, by the configured prices
+    pub(crate) cost: f64,
+}
+
+/// Every LLM call made during a turn.
+#[derive(Debug, Default)]
+pub(crate) struct Ledger {
+    records: Mutex<Vec<UsageRecord>>,
+}
+
+/// Usage of a tool added up over many calls.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ToolUsage {
+    pub(crate) calls: u64,
+    pub(crate) prompt_tokens: u64,
+    pub(crate) completion_tokens: u64,
+    pub(crate) audio_seconds: f64,
+    pub(crate) characters: u64,
+    pub(crate) images: u64,
+    pub(crate) cost: f64,
+}
+
+/// Usage of each tool per (UTC) day, so we know which tool costs what.
+#[derive(Debug)]
+pub(crate) struct UsageStats {
+    // "{date}/{tool}" => ToolUsage
+    tree: sled::Tree,
+}
+
+impl Usage {
+    pub(crate) fn cost(&self, prices: &PricesConfig) -> f64 {
+        match *self {
+            Usage::Chat {
+                prompt_tokens,
+                completion_tokens,
+            } => {
+                prompt_tokens as f64 / 1000.0 * prices.chat_input_per_1k
+                    + completion_tokens as f64 / 1000.0 * prices.chat_output_per_1k
+            }
+            Usage::Transcription { seconds } => seconds / 60.0 * prices.transcription_per_minute,
+            Usage::Speech { characters } => characters as f64 / 1000.0 * prices.speech_per_1k_chars,
+            Usage::Image { count } => count as f64 * prices.image,
+        }
+    }
+}
+
+impl Ledger {
+    pub(crate) fn record(&self, record: UsageRecord) {
+        self.records.lock().unwrap().push(record);
+    }
+
+    pub(crate) fn records(&self) -> Vec<UsageRecord> {
+        self.records.lock().unwrap().clone()
+    }
+}
+
+impl ToolUsage {
+    pub(crate) fn add(&mut self, usage: &Usage, cost: f64) {
+        self.calls += 1;
+        self.cost += cost;
+        match *usage {
+            Usage::Chat {
+                prompt_tokens,
+                completion_tokens,
+            } => {
+                self.prompt_tokens += prompt_tokens as u64;
+                self.completion_tokens += completion_tokens as u64;
+            }
+            Usage::Transcription { seconds } => self.audio_seconds += seconds,
+            Usage::Speech { characters } => self.characters += characters as u64,
+            Usage::Image { count } => self.images += count as u64,
+        }
+    }
+
+    fn merge(&mut self, other: &ToolUsage) {
+        self.calls += other.calls;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.audio_seconds += other.audio_seconds;
+        self.characters += other.characters;
+        self.images += other.images;
+        self.cost += other.cost;
+    }
+}
+
+impl UsageStats {
+    pub(crate) fn new(db: &sled::Db) -> anyhow::Result<Self> {
+        Ok(Self {
+            tree: db.open_tree("tool_usage")?,
+        })
+    }
+
+    /// Add the calls of a turn to today's usage of their tools.
+    pub(crate) fn add(&self, records: &[UsageRecord]) -> anyhow::Result<()> {
+        let mut by_tool: BTreeMap<&str, ToolUsage> = BTreeMap::new();
+        for record in records {
+            by_tool
+                .entry(&record.tool)
+                .or_default()
+                .add(&record.usage, record.cost);
+        }
+        let today = Utc::now().date_naive();
+        for (tool, usage) in by_tool {
+            self.tree
+                .update_and_fetch(format!("{}/{}", today, tool), |old| {
+                    let mut total: ToolUsage = old
+                        .and_then(|v| serde_json::from_slice(v).ok())
+                        .unwrap_or_default();
+                    total.merge(&usage);
+                    Some(serde_json::to_vec(&total).expect("tool usage shall serialize"))
+                })?;
+        }
+        Ok(())
+    }
+
+    /// Usage of each tool since `date`, the most expensive first.
+    pub(crate) fn by_tool(&self, since: NaiveDate) -> anyhow::Result<Vec<(String, ToolUsage)>> {
+        let mut by_tool: BTreeMap<String, ToolUsage> = BTreeMap::new();
+        // keys start with the date, which sorts like the date itself
+        for item in self.tree.range(since.to_string().as_bytes()..) {
+            let (key, value) = item?;
+            let key = String::from_utf8_lossy(&key);
+            let Some((_, tool)) = key.split_once('/') else {
+                continue;
+            };
+            let usage: ToolUsage = serde_json::from_slice(&value)?;
+            by_tool.entry(tool.to_string()).or_default().merge(&usage);
+        }
+        let mut by_tool: Vec<_> = by_tool.into_iter().collect();
+        by_tool.sort_by(|a, b| b.1.cost.total_cmp(&a.1.cost));
+        Ok(by_tool)
+    }
+}
+
+impl fmt::Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Usage::Chat {
+                prompt_tokens,
+                completion_tokens,
+            } => write!(
+                f,
+                "{} prompt + {} completion tokens",
+                prompt_tokens, completion_tokens
+            ),
+            Usage::Transcription { seconds } => write!(f, "{:.1}s of audio", seconds),
+            Usage::Speech { characters } => write!(f, "{} characters of speech", characters),
+            Usage::Image { count } => write!(f, "{} image(s)", count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(tool: &str, usage: Usage) -> UsageRecord {
+        let cost = usage.cost(&PricesConfig::default());
+        UsageRecord {
+            tool: tool.to_string(),
+            usage,
+            cost,
+        }
+    }
+
+    #[test]
+    fn usage_stats_should_add_up_per_tool() -> anyhow::Result<()> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let stats = UsageStats::new(&db)?;
+        let chat = Usage::Chat {
+            prompt_tokens: 1000,
+            completion_tokens: 1000,
+        };
+        let turn = [
+            record(ASSISTANT, Usage::Transcription { seconds: 30.0 }),
+            record(ASSISTANT, chat),
+            record("draw_image", Usage::Image { count: 1 }),
+        ];
+        stats.add(&turn)?;
+        stats.add(&turn[1..])?;
+
+        let by_tool = stats.by_tool(Utc::now().date_naive())?;
+        assert_eq!(by_tool.len(), 2);
+        let (tool, usage) = &by_tool[0];
+        assert_eq!(tool, ASSISTANT);
+        assert_eq!(usage.calls, 3);
+        assert_eq!(usage.prompt_tokens, 2000);
+        assert_eq!(usage.audio_seconds, 30.0);
+        assert!((usage.cost - 0.083).abs() < 1e-9);
+        assert_eq!(by_tool[1].1.images, 2);
+
+        let tomorrow = Utc::now().date_naive().succ_opt().unwrap();
+        assert!(stats.by_tool(tomorrow)?.is_empty());
+        Ok(())
+    }
+}
@@ -1,68 +1,244 @@
+mod assets;
+mod auth;
+mod channel;
+pub mod config;
+mod conversation;
+mod device;
 mod error;
 pub mod extractors;
 pub mod handlers;
+mod ledger;
+mod limits;
+pub mod llm;
+mod metrics;
+mod sentence;
+pub mod server;
+mod shutdown;
+mod storage;
+mod telemetry;
 pub mod tools;
 
-use std::{
-    env,
-    path::{Path, PathBuf},
+use std::{net::IpAddr, path::PathBuf, sync::Arc, time::Instant};
+
+use axum::{
+    routing::{get, post},
+    Router,
 };
 
+use assets::{open_blob_store, BlobStore};
+pub use assets::{run_janitor, BlobBackend};
+pub use auth::{run_session_sweeper, User};
+use auth::{AccountStore, OidcClient, SledAccountStore};
+pub use channel::run_channel_sweeper;
+use channel::EventChannel;
 use clap::Parser;
+use config::parse_ip_net;
+pub use config::AppConfig;
+use conversation::{Conversation, ConversationKey};
 use dashmap::DashMap;
+pub use device::DeviceId;
 pub use error::AppError;
-use handlers::AssistantEvent;
-use llm_sdk::LlmSdk;
-use tokio::sync::broadcast;
+use handlers::{
+    admin_presence_handler, admin_storage_handler, admin_usage_handler, assets_handler,
+    assistant_handler, events_handler, index_page, login_handler, login_page, logout_handler,
+    metrics_handler, oidc_callback_handler, oidc_login_handler, signup_handler, usage_handler,
+    ws_handler,
+};
+use ipnet::IpNet;
+use ledger::UsageStats;
+pub use limits::run_limits_sweeper;
+use limits::Limits;
+use llm::{LlmProviders, ProviderKind};
+use metrics::Metrics;
+use server::ServeMode;
+use shutdown::InFlight;
+use storage::{HistoryStore, SledHistoryStore};
+pub use telemetry::{init_tracing, TracingGuard};
+use tools::ToolRegistry;
+use tower_http::services::ServeDir;
 
 const COOKIE_NAME_DEVICE_ID: &str = "device_id";
 
+/// Command line args, each could also be set by an env var. They override the
+/// settings of the config file.
 #[derive(Debug, Parser)]
 #[clap(name = "ava")]
 pub struct Args {
-    #[clap(short, long, default_value = "8080")]
-    pub port: u16,
+    /// Path of the config file, `ava.toml` is used if it exists
+    #[clap(long, env = "AVA_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on
+    #[clap(long, env = "AVA_HOST")]
+    pub host: Option<IpAddr>,
+
+    #[clap(short, long, env = "AVA_PORT")]
+    pub port: Option<u16>,
+
+    /// Serve plain http, tls from cert_path or tls with a self-signed cert
+    #[clap(long, value_enum, env = "AVA_SERVE_MODE")]
+    pub mode: Option<ServeMode>,
+
+    #[clap(short, long, env = "AVA_CERT_PATH")]
+    pub cert_path: Option<PathBuf>,
+
+    /// Proxies whose `X-Forwarded-*` headers are trusted, as ip or cidr
+    #[clap(long, env = "AVA_TRUSTED_PROXIES", value_delimiter = ',', value_parser = parse_ip_net)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Bearer token of the /admin endpoints, they're disabled without it
+    #[clap(long, env = "AVA_ADMIN_TOKEN", hide_env_values = true)]
+    pub admin_token: Option<String>,
+
+    /// Directory of the static files
+    #[clap(long, env = "AVA_PUBLIC_DIR")]
+    pub public_dir: Option<PathBuf>,
+
+    /// Where the generated audio and images are kept
+    #[clap(long, value_enum, env = "AVA_STORAGE_BACKEND")]
+    pub storage_backend: Option<BlobBackend>,
+
+    /// Directory of the generated audio and images of the local backend
+    #[clap(long, env = "AVA_ASSETS_DIR")]
+    pub assets_dir: Option<PathBuf>,
+
+    /// Path of the conversation history database
+    #[clap(long, env = "AVA_DB_PATH")]
+    pub db_path: Option<PathBuf>,
+
+    /// Only let signed in users use the assistant
+    #[clap(long, env = "AVA_AUTH_REQUIRED")]
+    pub auth_required: Option<bool>,
+
+    /// Client secret of the OpenID Connect provider
+    #[clap(long, env = "AVA_OIDC_CLIENT_SECRET", hide_env_values = true)]
+    pub oidc_client_secret: Option<String>,
+
+    /// OTLP (grpc) endpoint to export the spans to, e.g. http://localhost:4317
+    #[clap(long, env = "AVA_OTLP_ENDPOINT")]
+    pub otlp_endpoint: Option<String>,
+
+    /// File to write the spans to as json lines, if there's no OTLP endpoint
+    #[clap(long, env = "AVA_TRACE_FILE")]
+    pub trace_file: Option<PathBuf>,
+
+    /// Provider for chat completion
+    #[clap(long, value_enum, env = "AVA_CHAT_PROVIDER")]
+    pub chat_provider: Option<ProviderKind>,
 
-    #[clap(short, long, default_value = "./.certs")]
-    pub cert_path: String,
+    /// Provider for tool calling
+    #[clap(long, value_enum, env = "AVA_TOOLS_PROVIDER")]
+    pub tools_provider: Option<ProviderKind>,
+
+    /// Provider for audio transcription
+    #[clap(long, value_enum, env = "AVA_TRANSCRIPTION_PROVIDER")]
+    pub transcription_provider: Option<ProviderKind>,
+
+    /// Provider for text to speech
+    #[clap(long, value_enum, env = "AVA_SPEECH_PROVIDER")]
+    pub speech_provider: Option<ProviderKind>,
+
+    /// Provider for image generation
+    #[clap(long, value_enum, env = "AVA_IMAGE_PROVIDER")]
+    pub image_provider: Option<ProviderKind>,
+
+    /// Base url of the OpenAI API
+    #[clap(long, env = "OPENAI_BASE_URL")]
+    pub openai_base_url: Option<String>,
+
+    /// Api key of the OpenAI API
+    #[clap(long, env = "OPENAI_API_KEY", hide_env_values = true)]
+    pub openai_api_key: Option<String>,
+
+    /// Base url of the OpenAI compatible local endpoint
+    #[clap(long, env = "AVA_LOCAL_URL")]
+    pub local_url: Option<String>,
+
+    /// Api key of the local endpoint, if it requires one
+    #[clap(long, env = "AVA_LOCAL_API_KEY", hide_env_values = true)]
+    pub local_api_key: Option<String>,
+
+    /// Model served by the local endpoint
+    #[clap(long, env = "AVA_LOCAL_MODEL")]
+    pub local_model: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct AppState {
-    pub(crate) llm: LlmSdk,
-    // each device_id has a channel to send messages to
-    pub(crate) events: DashMap<String, broadcast::Sender<AssistantEvent>>,
+    pub(crate) config: AppConfig,
+    pub(crate) llm: LlmProviders,
+    // each device_id has a channel to send messages to, created on demand
+    pub(crate) events: DashMap<DeviceId, Arc<EventChannel>>,
+    // id of the last event of the devices whose channel was removed and when,
+    // the ids go on from it when the channel is created again
+    pub(crate) last_event_ids: DashMap<DeviceId, (u64, Instant)>,
+    // chat history of each user or anonymous device, fed into every completion
+    pub(crate) conversations: DashMap<ConversationKey, Conversation>,
+    // persisted turns, rendered on the index page
+    pub(crate) history: Box<dyn HistoryStore>,
+    // user accounts, their devices and sessions
+    pub(crate) accounts: Box<dyn AccountStore>,
+    pub(crate) oidc: Option<OidcClient>,
+    // rate limits and daily spend of each user
+    pub(crate) limits: Limits,
+    // what each tool used per day
+    pub(crate) usage: UsageStats,
+    // generated audio and images
+    pub(crate) blobs: Box<dyn BlobStore>,
+    // tools the model can call while answering
+    pub(crate) tools: ToolRegistry,
+    // served on /metrics, shared with the llm providers
+    pub(crate) metrics: Arc<Metrics>,
+    // running assistant pipelines, drained on shutdown
+    pub(crate) in_flight: InFlight,
 }
 
-impl Default for AppState {
-    fn default() -> Self {
-        Self {
-            llm: LlmSdk::new(
-                "https://api.openai.com/v1",
-                env::var("OPENAI_API_KEY").unwrap(),
-                3,
-            ),
-            events: DashMap::new(),
-        }
+impl AppState {
+    pub fn new(config: AppConfig) -> anyhow::Result<Self> {
+        let llm = LlmProviders::new(&config)?;
+        Self::with_llm(config, llm)
     }
-}
-
-pub fn audio_path(device_id: &str, name: &str) -> PathBuf {
-    Path::new("/tmp/ava-bot/audio")
-        .join(device_id)
-        .join(format!("{}.mp3", name))
-}
 
-pub fn audio_url(device_id: &str, name: &str) -> String {
-    format!("/assets/audio/{}/{}.mp3", device_id, name)
-}
-
-pub fn image_path(device_id: &str, name: &str) -> PathBuf {
-    Path::new("/tmp/ava-bot/image")
-        .join(device_id)
-        .join(format!("{}.png", name))
+    pub fn with_llm(config: AppConfig, llm: LlmProviders) -> anyhow::Result<Self> {
+        let db = sled::open(&config.storage.db_path)?;
+        let metrics = Arc::new(Metrics::new()?);
+        Ok(Self {
+            history: Box::new(SledHistoryStore::new(db.clone())),
+            accounts: Box::new(SledAccountStore::new(&db)?),
+            oidc: config.auth.oidc.clone().map(OidcClient::new),
+            limits: Limits::new(config.limits.clone(), &db)?,
+            usage: UsageStats::new(&db)?,
+            blobs: open_blob_store(&config.storage)?,
+            config,
+            llm: llm.metered(&metrics),
+            events: DashMap::new(),
+            last_event_ids: DashMap::new(),
+            conversations: DashMap::new(),
+            tools: ToolRegistry::new(),
+            metrics,
+            in_flight: InFlight::default(),
+        })
+    }
 }
 
-pub fn image_url(device_id: &str, name: &str) -> String {
-    format!("/assets/image/{}/{}.png", device_id, name)
+pub fn router(state: Arc<AppState>) -> Router {
+    let public_dir = state.config.server.public_dir.clone();
+    Router::new()
+        .route("/", get(index_page))
+        .route("/events", get(events_handler))
+        .route("/assistant", post(assistant_handler))
+        .route("/ws", get(ws_handler))
+        .route("/login", get(login_page).post(login_handler))
+        .route("/signup", post(signup_handler))
+        .route("/logout", post(logout_handler))
+        .route("/auth/oidc/login", get(oidc_login_handler))
+        .route("/auth/oidc/callback", get(oidc_callback_handler))
+        .route("/usage", get(usage_handler))
+        .route("/metrics", get(metrics_handler))
+        .route("/admin/presence", get(admin_presence_handler))
+        .route("/admin/storage", get(admin_storage_handler))
+        .route("/admin/usage", get(admin_usage_handler))
+        .nest_service("/public", ServeDir::new(public_dir))
+        .route("/assets/:kind/:device_id/:name", get(assets_handler))
+        .with_state(state)
 }
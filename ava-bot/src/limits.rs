@@ -0,0 +1,392 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{NaiveDate, Utc};
+use dashmap::DashMap;
+use serde::Serialize;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    config::LimitsConfig,
+    extractors::{AppContext, Locale},
+    ledger::{Ledger, Usage, UsageRecord, ASSISTANT},
+    AppState, DeviceId,
+};
+
+/// window of `requests_per_minute`
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+/// how often the spend of past days is forgotten
+const SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Whose requests and spend are counted together: a signed in user across all
+/// the devices, or an anonymous client by its address, as a new device is
+/// only a dropped cookie away.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum UsageKey {
+    User(Uuid),
+    Ip(IpAddr),
+    /// an anonymous client whose address is unknown
+    Device(DeviceId),
+}
+
+/// Why a turn is refused, shown to the user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LimitExceeded {
+    Rate { retry_after: Duration },
+    Budget,
+}
+
+/// Rate limits and daily budgets of every user.
+#[derive(Debug)]
+pub(crate) struct Limits {
+    config: LimitsConfig,
+    // start times of the turns in the last minute, pruned by the sweeper
+    requests: DashMap<UsageKey, VecDeque<Instant>>,
+    // "{date}/{key}" => dollars spent on that (UTC) day
+    spend: sled::Tree,
+}
+
+/// Charges the LLM calls of a turn to the budget of its user, and records
+/// them in the ledger of the turn.
+#[derive(Debug)]
+pub(crate) struct Meter<'a> {
+    limits: &'a Limits,
+    key: UsageKey,
+    ledger: &'a Ledger,
+    // the tool the calls are made for
+    tool: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct UsageSummary {
+    pub(crate) key: String,
+    pub(crate) spent_today: f64,
+    /// None if there's no daily budget
+    pub(crate) remaining_today: Option<f64>,
+    pub(crate) requests_last_minute: usize,
+    pub(crate) requests_per_minute: Option<u32>,
+}
+
+impl Limits {
+    pub(crate) fn new(config: LimitsConfig, db: &sled::Db) -> anyhow::Result<Self> {
+        Ok(Self {
+            config,
+            requests: DashMap::new(),
+            spend: db.open_tree("spend")?,
+        })
+    }
+
+    /// Count a new turn in, unless the user is over the rate limit or has
+    /// used up today's budget.
+    pub(crate) fn admit(&self, key: &UsageKey) -> anyhow::Result<()> {
+        if self.config.daily_budget > 0.0 && self.spent_today(key)? >= self.config.daily_budget {
+            return Err(LimitExceeded::Budget.into());
+        }
+        let limit = self.config.requests_per_minute as usize;
+        if limit == 0 {
+            return Ok(());
+        }
+        let now = Instant::now();
+        let mut requests = self.requests.entry(key.clone()).or_default();
+        while let Some(start) = requests.front() {
+            if now.duration_since(*start) < RATE_WINDOW {
+                break;
+            }
+            requests.pop_front();
+        }
+        if requests.len() >= limit {
+            let oldest = requests.front().copied().unwrap_or(now);
+            let retry_after = RATE_WINDOW.saturating_sub(now.duration_since(oldest));
+            return Err(LimitExceeded::Rate { retry_after }.into());
+        }
+        requests.push_back(now);
+        Ok(())
+    }
+
+    pub(crate) fn meter<'a>(&'a self, key: UsageKey, ledger: &'a Ledger) -> Meter<'a> {
+        Meter {
+            limits: self,
+            key,
+            ledger,
+            tool: ASSISTANT,
+        }
+    }
+
+    pub(crate) fn charge(&self, key: &UsageKey, cost: f64) -> anyhow::Result<()> {
+        if cost <= 0.0 {
+            return Ok(());
+        }
+        self.spend
+            .update_and_fetch(spend_key(today(), key), |old| {
+                let spent = old.map(decode).unwrap_or_default() + cost;
+                Some(spent.to_be_bytes().to_vec())
+            })?;
+        Ok(())
+    }
+
+    pub(crate) fn spent_today(&self, key: &UsageKey) -> anyhow::Result<f64> {
+        let spent = self.spend.get(spend_key(today(), key))?;
+        Ok(spent.as_deref().map(decode).unwrap_or_default())
+    }
+
+    pub(crate) fn summary(&self, key: &UsageKey) -> anyhow::Result<UsageSummary> {
+        let spent_today = self.spent_today(key)?;
+        let budget = self.config.daily_budget;
+        let rpm = self.config.requests_per_minute;
+        let requests_last_minute = self.requests.get(key).map_or(0, |v| {
+            v.iter()
+                .filter(|start| start.elapsed() < RATE_WINDOW)
+                .count()
+        });
+        Ok(UsageSummary {
+            key: key.to_string(),
+            spent_today,
+            remaining_today: (budget > 0.0).then(|| (budget - spent_today).max(0.0)),
+            requests_last_minute,
+            requests_per_minute: (rpm > 0).then_some(rpm),
+        })
+    }
+
+    /// Dollars spent today by each user, the biggest spender first.
+    pub(crate) fn spent_today_by_key(&self) -> anyhow::Result<Vec<(String, f64)>> {
+        let prefix = format!("{}/", today());
+        let mut spent = self
+            .spend
+            .scan_prefix(&prefix)
+            .map(|item| {
+                let (key, value) = item?;
+                let key = String::from_utf8_lossy(&key[prefix.len()..]).to_string();
+                Ok((key, decode(&value)))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        spent.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Ok(spent)
+    }
+
+    /// Drop the windows with no request in the last minute, returns how many
+    /// are dropped.
+    pub(crate) fn prune_requests(&self, now: Instant) -> usize {
+        let before = self.requests.len();
+        self.requests.retain(|_, v| {
+            v.back()
+                .is_some_and(|start| now.duration_since(*start) < RATE_WINDOW)
+        });
+        before.saturating_sub(self.requests.len())
+    }
+
+    /// Forget the spend of the days before `date`, returns how many records
+    /// are removed.
+    pub(crate) fn delete_spend_before(&self, date: NaiveDate) -> anyhow::Result<usize> {
+        let before = date.to_string();
+        let mut count = 0;
+        // keys start with the date, which sorts like the date itself
+        for item in self.spend.range(..before.as_bytes()) {
+            let (key, _) = item?;
+            self.spend.remove(key)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Forget the spend of past days and the idle rate windows periodically,
+/// until the server stops.
+pub async fn run_limits_sweeper(state: Arc<AppState>) {
+    let mut ticker = tokio::time::interval(SWEEP_INTERVAL);
+    loop {
+        ticker.tick().await;
+        state.limits.prune_requests(Instant::now());
+        // only today's spend counts, the day before is kept for the day change
+        let yesterday = today() - chrono::Duration::days(1);
+        match state.limits.delete_spend_before(yesterday) {
+            Ok(0) => {}
+            Ok(removed) => info!("removed {} spend records of past days", removed),
+            Err(e) => warn!("failed to clean up spend: {}", e),
+        }
+    }
+}
+
+impl<'a> Meter<'a> {
+    /// The same meter, with the calls recorded for `tool`.
+    pub(crate) fn for_tool(&self, tool: &'a str) -> Self {
+        Self {
+            key: self.key.clone(),
+            tool,
+            ..*self
+        }
+    }
+
+    /// calls recorded in the ledger so far
+    pub(crate) fn records(&self) -> Vec<UsageRecord> {
+        self.ledger.records()
+    }
+
+    /// chat completion and tool calling
+    pub(crate) fn chat(&self, prompt_tokens: usize, completion_tokens: usize) {
+        self.record(Usage::Chat {
+            prompt_tokens,
+            completion_tokens,
+        });
+    }
+
+    pub(crate) fn transcription(&self, seconds: f64) {
+        self.record(Usage::Transcription { seconds });
+    }
+
+    pub(crate) fn speech(&self, characters: usize) {
+        self.record(Usage::Speech { characters });
+    }
+
+    pub(crate) fn image(&self) {
+        self.record(Usage::Image { count: 1 });
+    }
+
+    // the call is made already, failing to charge it shouldn't fail the turn
+    fn record(&self, usage: Usage) {
+        let cost = usage.cost(&self.limits.config.prices);
+        if let Err(e) = self.limits.charge(&self.key, cost) {
+            warn!("failed to charge {} to {}: {}", cost, self.key, e);
+        }
+        self.ledger.record(UsageRecord {
+            tool: self.tool.to_string(),
+            usage,
+            cost,
+        });
+    }
+}
+
+impl From<&AppContext> for UsageKey {
+    fn from(context: &AppContext) -> Self {
+        match (&context.user, context.ip) {
+            (Some(user), _) => UsageKey::User(user.id),
+            (None, Some(ip)) => UsageKey::Ip(ip),
+            (None, None) => UsageKey::Device(context.device_id),
+        }
+    }
+}
+
+impl fmt::Display for UsageKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsageKey::User(id) => write!(f, "user:{}", id),
+            UsageKey::Ip(ip) => write!(f, "ip:{}", ip),
+            UsageKey::Device(id) => write!(f, "device:{}", id),
+        }
+    }
+}
+
+impl LimitExceeded {
+    /// Why the turn is refused, in the language of the user.
+    pub(crate) fn message(&self, locale: Locale) -> String {
+        match (self, locale) {
+            (LimitExceeded::Rate { retry_after }, Locale::En) => format!(
+                "You're asking a bit too fast, please try again in {} seconds.",
+                retry_after.as_secs().max(1)
+            ),
+            (LimitExceeded::Rate { retry_after }, Locale::Zh) => format!(
+                "请求太频繁了，请 {} 秒后再试。",
+                retry_after.as_secs().max(1)
+            ),
+            (LimitExceeded::Budget, Locale::En) => {
+                "You've used up today's budget, please come back tomorrow.".to_string()
+            }
+            (LimitExceeded::Budget, Locale::Zh) => "今天的额度已用完，请明天再来。".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message(Locale::En))
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+fn today() -> NaiveDate {
+    Utc::now().date_naive()
+}
+
+fn spend_key(date: NaiveDate, key: &UsageKey) -> String {
+    format!("{}/{}", date, key)
+}
+
+fn decode(value: impl AsRef<[u8]>) -> f64 {
+    value
+        .as_ref()
+        .try_into()
+        .map(f64::from_be_bytes)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PricesConfig;
+
+    fn limits(requests_per_minute: u32, daily_budget: f64) -> anyhow::Result<Limits> {
+        let db = sled::Config::new().temporary(true).open()?;
+        let config = LimitsConfig {
+            requests_per_minute,
+            daily_budget,
+            prices: PricesConfig::default(),
+        };
+        Limits::new(config, &db)
+    }
+
+    fn exceeded(ret: anyhow::Result<()>) -> Option<LimitExceeded> {
+        ret.err()?.downcast().ok()
+    }
+
+    #[test]
+    fn limits_should_refuse_requests_over_rate() -> anyhow::Result<()> {
+        let limits = limits(2, 0.0)?;
+        let (a, b) = (
+            UsageKey::Device(DeviceId::new()),
+            UsageKey::Device(DeviceId::new()),
+        );
+        limits.admit(&a)?;
+        limits.admit(&a)?;
+        assert!(matches!(
+            exceeded(limits.admit(&a)),
+            Some(LimitExceeded::Rate { .. })
+        ));
+        limits.admit(&b)?;
+        assert_eq!(limits.summary(&a)?.requests_last_minute, 2);
+
+        assert_eq!(limits.prune_requests(Instant::now()), 0);
+        assert_eq!(limits.prune_requests(Instant::now() + RATE_WINDOW), 2);
+        limits.admit(&a)?;
+        Ok(())
+    }
+
+    #[test]
+    fn limits_should_refuse_requests_over_budget() -> anyhow::Result<()> {
+        let limits = limits(0, 0.05)?;
+        let key = UsageKey::User(Uuid::new_v4());
+        limits.admit(&key)?;
+        let ledger = Ledger::default();
+        let meter = limits.meter(key.clone(), &ledger);
+        meter.for_tool("draw_image").image();
+        meter.chat(1000, 1000);
+        assert_eq!(exceeded(limits.admit(&key)), Some(LimitExceeded::Budget));
+        let tools: Vec<_> = ledger.records().into_iter().map(|v| v.tool).collect();
+        assert_eq!(tools, ["draw_image", ASSISTANT]);
+
+        let summary = limits.summary(&key)?;
+        assert!((summary.spent_today - 0.08).abs() < 1e-9);
+        assert_eq!(summary.remaining_today, Some(0.0));
+        assert_eq!(limits.spent_today_by_key()?[0].0, key.to_string());
+
+        let tomorrow = today().succ_opt().unwrap();
+        assert_eq!(limits.delete_spend_before(tomorrow)?, 1);
+        limits.admit(&key)?;
+        Ok(())
+    }
+}
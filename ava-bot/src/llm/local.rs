@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Context};
+use axum::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use llm_sdk::{
+    chat_completion::{ChatCompletionRequest, ChatCompletionResponse},
+    create_image::{CreateImageRequest, CreateImageResponse},
+    speech::SpeechRequest,
+    whisper::{WhisperRequest, WhisperResponse},
+    LlmSdk,
+};
+use serde_json::{json, Value};
+
+use super::{stream::stream_chat_completion, LlmProvider};
+
+/// An OpenAI compatible endpoint (Ollama, llama.cpp server, LocalAI, etc.).
+///
+/// Chat completions are sent with the configured model name since local
+/// servers don't know OpenAI's models; the other capabilities are forwarded as is.
+#[derive(Debug)]
+pub struct LocalProvider {
+    base_url: String,
+    api_key: Option<String>,
+    model: String,
+    client: reqwest::Client,
+    sdk: LlmSdk,
+}
+
+impl LocalProvider {
+    pub fn new(base_url: &str, api_key: Option<&str>, model: impl Into<String>) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        Self {
+            sdk: LlmSdk::new(&base_url, api_key.unwrap_or_default(), 0),
+            base_url,
+            api_key: api_key.map(|v| v.to_string()),
+            model: model.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for LocalProvider {
+    async fn chat_completion(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<ChatCompletionResponse> {
+        let mut body = serde_json::to_value(req)?;
+        // keep the model name llm-sdk understands for parsing the response back
+        let model = body["model"].take();
+        body["model"] = json!(self.model);
+
+        let mut builder = self
+            .client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(&body);
+        if let Some(api_key) = &self.api_key {
+            builder = builder.bearer_auth(api_key);
+        }
+        let res = builder.send().await?;
+        let status = res.status();
+        if !status.is_success() {
+            let text = res.text().await.unwrap_or_default();
+            return Err(anyhow!("local llm returned {}: {}", status, text));
+        }
+
+        let mut value: Value = res.json().await?;
+        normalize_response(&mut value, model);
+        serde_json::from_value(value).context("failed to parse local llm response")
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<String>>> {
+        let mut body = serde_json::to_value(req)?;
+        body["model"] = json!(self.model);
+        stream_chat_completion(
+            &self.client,
+            &format!("{}/chat/completions", self.base_url),
+            self.api_key.as_deref(),
+            body,
+        )
+        .await
+    }
+
+    async fn whisper(&self, req: WhisperRequest) -> anyhow::Result<WhisperResponse> {
+        self.sdk.whisper(req).await
+    }
+
+    async fn speech(&self, req: SpeechRequest) -> anyhow::Result<Bytes> {
+        self.sdk.speech(req).await
+    }
+
+    async fn create_image(&self, req: CreateImageRequest) -> anyhow::Result<CreateImageResponse> {
+        self.sdk.create_image(req).await
+    }
+}
+
+/// Local servers omit or vary some fields of OpenAI's response, fill them so
+/// the response could be parsed by llm-sdk.
+fn normalize_response(value: &mut Value, model: Value) {
+    value["model"] = model;
+    for (key, default) in [
+        ("id", json!("")),
+        ("object", json!("chat.completion")),
+        ("created", json!(0)),
+        ("system_fingerprint", json!("")),
+    ] {
+        if value[key].is_null() {
+            value[key] = default;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_response_should_fill_missing_fields() {
+        let mut value = json!({
+            "model": "llama2",
+            "created": 1700000000,
+            "choices": [],
+        });
+        normalize_response(&mut value, json!("gpt-4-1106-preview"));
+        assert_eq!(value["model"], "gpt-4-1106-preview");
+        assert_eq!(value["created"], 1700000000);
+        assert_eq!(value["system_fingerprint"], "");
+    }
+}
@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use llm_sdk::{
+    chat_completion::{ChatCompletionRequest, ChatCompletionResponse},
+    create_image::{CreateImageRequest, CreateImageResponse},
+    speech::SpeechRequest,
+    whisper::{WhisperRequest, WhisperResponse},
+};
+use tracing::{info_span, Instrument, Span};
+
+use crate::{error::AssistantError, metrics::Metrics};
+
+use super::LlmProvider;
+
+/// Counts the requests to a provider and their errors, labeled by the
+/// capability the provider is used for. Each request is traced in a span,
+/// and its failure is raised as an upstream error.
+#[derive(Debug)]
+pub(crate) struct MeteredProvider {
+    inner: Arc<dyn LlmProvider>,
+    capability: &'static str,
+    metrics: Arc<Metrics>,
+}
+
+impl MeteredProvider {
+    pub(crate) fn new(
+        inner: Arc<dyn LlmProvider>,
+        capability: &'static str,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            inner,
+            capability,
+            metrics,
+        }
+    }
+
+    fn span(&self, method: &'static str) -> Span {
+        info_span!("llm", capability = self.capability, method)
+    }
+
+    fn upstream(&self, err: anyhow::Error) -> anyhow::Error {
+        AssistantError::upstream(self.capability, err).into()
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MeteredProvider {
+    async fn chat_completion(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<ChatCompletionResponse> {
+        self.metrics
+            .llm_request(self.capability, self.inner.chat_completion(req))
+            .instrument(self.span("chat_completion"))
+            .await
+            .map_err(|e| self.upstream(e))
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<String>>> {
+        let stream = self
+            .metrics
+            .llm_request(self.capability, self.inner.chat_completion_stream(req))
+            .instrument(self.span("chat_completion_stream"))
+            .await
+            .map_err(|e| self.upstream(e))?;
+        // the stream could still break after the request is accepted
+        let (metrics, capability) = (self.metrics.clone(), self.capability);
+        Ok(stream
+            .map(move |v| {
+                v.map_err(|e| {
+                    metrics.llm_error(capability);
+                    AssistantError::upstream(capability, e).into()
+                })
+            })
+            .boxed())
+    }
+
+    async fn whisper(&self, req: WhisperRequest) -> anyhow::Result<WhisperResponse> {
+        self.metrics
+            .llm_request(self.capability, self.inner.whisper(req))
+            .instrument(self.span("whisper"))
+            .await
+            .map_err(|e| self.upstream(e))
+    }
+
+    async fn speech(&self, req: SpeechRequest) -> anyhow::Result<Bytes> {
+        self.metrics
+            .llm_request(self.capability, self.inner.speech(req))
+            .instrument(self.span("speech"))
+            .await
+            .map_err(|e| self.upstream(e))
+    }
+
+    async fn create_image(&self, req: CreateImageRequest) -> anyhow::Result<CreateImageResponse> {
+        self.metrics
+            .llm_request(self.capability, self.inner.create_image(req))
+            .instrument(self.span("create_image"))
+            .await
+            .map_err(|e| self.upstream(e))
+    }
+}
@@ -0,0 +1,167 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::bail;
+use axum::async_trait;
+use bytes::Bytes;
+use llm_sdk::{
+    chat_completion::{ChatCompletionRequest, ChatCompletionResponse},
+    create_image::{CreateImageRequest, CreateImageResponse},
+    speech::SpeechRequest,
+    whisper::{WhisperRequest, WhisperResponse},
+};
+use serde_json::{json, Value};
+
+use super::LlmProvider;
+
+// an empty ID3 header, nobody is going to play it
+const MOCK_MP3: &[u8] = b"ID3\x03\x00\x00\x00\x00\x00\x00";
+// 1x1 transparent png
+const MOCK_PNG_B64: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAQAAAC1HAwCAAAAC0lEQVR42mNkYAAAAAYAAjCB0C8AAAAASUVORK5CYII=";
+
+/// A scripted, deterministic provider for offline testing. It never calls
+/// any remote service.
+#[derive(Debug)]
+pub struct MockProvider {
+    transcript: String,
+    // (function name, json arguments) returned when tools are provided
+    tool_calls: Vec<(String, String)>,
+    reply: String,
+    // final words after the tools are called
+    summary: String,
+    // the image provider fails with it if set
+    image_error: Option<String>,
+    calls: AtomicUsize,
+}
+
+impl Default for MockProvider {
+    fn default() -> Self {
+        Self::new("Hello Ava").with_tool_call("answer", json!({"prompt": "Hello Ava"}))
+    }
+}
+
+impl MockProvider {
+    pub fn new(transcript: impl Into<String>) -> Self {
+        Self {
+            transcript: transcript.into(),
+            tool_calls: vec![],
+            reply: "Hello, I'm Ava.".to_string(),
+            summary: String::new(),
+            image_error: None,
+            calls: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn with_tool_call(mut self, name: impl Into<String>, args: Value) -> Self {
+        self.tool_calls.push((name.into(), args.to_string()));
+        self
+    }
+
+    pub fn with_summary(mut self, summary: impl Into<String>) -> Self {
+        self.summary = summary.into();
+        self
+    }
+
+    pub fn with_reply(mut self, reply: impl Into<String>) -> Self {
+        self.reply = reply.into();
+        self
+    }
+
+    pub fn with_image_error(mut self, error: impl Into<String>) -> Self {
+        self.image_error = Some(error.into());
+        self
+    }
+
+    fn next_id(&self) -> usize {
+        self.calls.fetch_add(1, Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl LlmProvider for MockProvider {
+    async fn chat_completion(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<ChatCompletionResponse> {
+        let body = serde_json::to_value(req)?;
+        let id = self.next_id();
+        let has_tools = body["tools"].as_array().is_some_and(|v| !v.is_empty());
+        let after_tool = body["messages"]
+            .as_array()
+            .and_then(|v| v.last())
+            .is_some_and(|v| v["role"] == "tool");
+
+        let (finish_reason, message) = if after_tool {
+            (
+                "stop",
+                json!({ "role": "assistant", "content": self.summary }),
+            )
+        } else if has_tools && !self.tool_calls.is_empty() {
+            let tool_calls: Vec<_> = self
+                .tool_calls
+                .iter()
+                .enumerate()
+                .map(|(i, (name, arguments))| {
+                    json!({
+                        "id": format!("call_{}_{}", id, i),
+                        "type": "function",
+                        "function": { "name": name, "arguments": arguments },
+                    })
+                })
+                .collect();
+            (
+                "tool_calls",
+                json!({ "role": "assistant", "content": null, "tool_calls": tool_calls }),
+            )
+        } else {
+            (
+                "stop",
+                json!({ "role": "assistant", "content": self.reply }),
+            )
+        };
+
+        let prompt_tokens = body["messages"].to_string().len() / 4;
+        let completion_tokens = message.to_string().len() / 4;
+        let res = json!({
+            "id": format!("mock-{}", id),
+            "object": "chat.completion",
+            "created": 0,
+            "model": body["model"],
+            "system_fingerprint": "mock",
+            "choices": [{
+                "index": 0,
+                "finish_reason": finish_reason,
+                "message": message,
+            }],
+            "usage": {
+                "prompt_tokens": prompt_tokens,
+                "completion_tokens": completion_tokens,
+                "total_tokens": prompt_tokens + completion_tokens,
+            },
+        });
+        Ok(serde_json::from_value(res)?)
+    }
+
+    async fn whisper(&self, _req: WhisperRequest) -> anyhow::Result<WhisperResponse> {
+        Ok(serde_json::from_value(json!({ "text": self.transcript }))?)
+    }
+
+    async fn speech(&self, _req: SpeechRequest) -> anyhow::Result<Bytes> {
+        Ok(Bytes::from_static(MOCK_MP3))
+    }
+
+    async fn create_image(&self, req: CreateImageRequest) -> anyhow::Result<CreateImageResponse> {
+        if let Some(error) = &self.image_error {
+            bail!("{}", error);
+        }
+        let body = serde_json::to_value(req)?;
+        let res = json!({
+            "created": 0,
+            "data": [{
+                "b64_json": MOCK_PNG_B64,
+                "revised_prompt": body["prompt"],
+            }],
+        });
+        Ok(serde_json::from_value(res)?)
+    }
+}
@@ -0,0 +1,153 @@
+mod local;
+mod metered;
+mod mock;
+mod openai;
+mod stream;
+
+use std::{fmt::Debug, sync::Arc};
+
+use axum::async_trait;
+use bytes::Bytes;
+use clap::ValueEnum;
+use futures::{stream::BoxStream, StreamExt};
+use llm_sdk::{
+    chat_completion::{ChatCompletionRequest, ChatCompletionResponse},
+    create_image::{CreateImageRequest, CreateImageResponse},
+    speech::SpeechRequest,
+    whisper::{WhisperRequest, WhisperResponse},
+};
+use serde::Deserialize;
+
+use crate::{metrics::Metrics, AppConfig};
+
+use metered::MeteredProvider;
+
+pub use local::LocalProvider;
+pub use mock::MockProvider;
+pub use openai::OpenAiProvider;
+
+/// A backend that serves the LLM capabilities Ava relies on. Tool calling is a
+/// chat completion with tools attached.
+#[async_trait]
+pub trait LlmProvider: Debug + Send + Sync {
+    async fn chat_completion(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<ChatCompletionResponse>;
+
+    /// Stream the content of a chat completion as it's generated. Providers
+    /// which can't stream yield the whole content at once.
+    async fn chat_completion_stream(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<String>>> {
+        let mut res = self.chat_completion(req).await?;
+        let content = res
+            .choices
+            .pop()
+            .and_then(|v| v.message.content)
+            .unwrap_or_default();
+        Ok(futures::stream::once(async move { Ok(content) }).boxed())
+    }
+
+    async fn whisper(&self, req: WhisperRequest) -> anyhow::Result<WhisperResponse>;
+
+    async fn speech(&self, req: SpeechRequest) -> anyhow::Result<Bytes>;
+
+    async fn create_image(&self, req: CreateImageRequest) -> anyhow::Result<CreateImageResponse>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// OpenAI API
+    Openai,
+    /// OpenAI compatible local endpoint, e.g. Ollama or llama.cpp server
+    Local,
+    /// Scripted responses for offline testing
+    Mock,
+}
+
+/// The provider used for each capability.
+#[derive(Debug, Clone)]
+pub struct LlmProviders {
+    pub chat: Arc<dyn LlmProvider>,
+    pub tools: Arc<dyn LlmProvider>,
+    pub transcription: Arc<dyn LlmProvider>,
+    pub speech: Arc<dyn LlmProvider>,
+    pub image: Arc<dyn LlmProvider>,
+}
+
+impl LlmProviders {
+    /// Create the providers selected by config. A provider is only built when
+    /// at least one capability uses it.
+    pub fn new(config: &AppConfig) -> anyhow::Result<Self> {
+        let mut openai: Option<Arc<dyn LlmProvider>> = None;
+        let mut local: Option<Arc<dyn LlmProvider>> = None;
+        let mut mock: Option<Arc<dyn LlmProvider>> = None;
+        let mut get = |kind: ProviderKind| -> anyhow::Result<Arc<dyn LlmProvider>> {
+            let provider = match kind {
+                ProviderKind::Openai => {
+                    if openai.is_none() {
+                        openai = Some(Arc::new(OpenAiProvider::new(
+                            &config.openai.base_url,
+                            config.openai.api_key.as_deref().unwrap_or_default(),
+                            config.openai.max_retries,
+                        )));
+                    }
+                    openai.clone()
+                }
+                ProviderKind::Local => {
+                    if local.is_none() {
+                        local = Some(Arc::new(LocalProvider::new(
+                            &config.local.url,
+                            config.local.api_key.as_deref(),
+                            &config.local.model,
+                        )));
+                    }
+                    local.clone()
+                }
+                ProviderKind::Mock => {
+                    if mock.is_none() {
+                        mock = Some(Arc::new(MockProvider::default()));
+                    }
+                    mock.clone()
+                }
+            };
+            Ok(provider.expect("provider shall be initialized"))
+        };
+
+        Ok(Self {
+            chat: get(config.providers.chat)?,
+            tools: get(config.providers.tools)?,
+            transcription: get(config.providers.transcription)?,
+            speech: get(config.providers.speech)?,
+            image: get(config.providers.image)?,
+        })
+    }
+
+    /// Use the same provider for every capability.
+    pub fn from_provider(provider: Arc<dyn LlmProvider>) -> Self {
+        Self {
+            chat: provider.clone(),
+            tools: provider.clone(),
+            transcription: provider.clone(),
+            speech: provider.clone(),
+            image: provider,
+        }
+    }
+
+    /// Count the requests of each capability into `metrics`.
+    pub(crate) fn metered(self, metrics: &Arc<Metrics>) -> Self {
+        let metered = |provider: Arc<dyn LlmProvider>, capability| -> Arc<dyn LlmProvider> {
+            Arc::new(MeteredProvider::new(provider, capability, metrics.clone()))
+        };
+        Self {
+            chat: metered(self.chat, "chat"),
+            tools: metered(self.tools, "tools"),
+            transcription: metered(self.transcription, "transcription"),
+            speech: metered(self.speech, "speech"),
+            image: metered(self.image, "image"),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use axum::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use llm_sdk::{
+    chat_completion::{ChatCompletionRequest, ChatCompletionResponse},
+    create_image::{CreateImageRequest, CreateImageResponse},
+    speech::SpeechRequest,
+    whisper::{WhisperRequest, WhisperResponse},
+    LlmSdk,
+};
+
+use super::{stream::stream_chat_completion, LlmProvider};
+
+#[derive(Debug)]
+pub struct OpenAiProvider {
+    sdk: LlmSdk,
+    base_url: String,
+    // llm-sdk doesn't support streaming yet, streamed requests are sent directly
+    api_key: String,
+    client: reqwest::Client,
+}
+
+impl OpenAiProvider {
+    pub fn new(base_url: &str, api_key: impl Into<String>, max_retries: u32) -> Self {
+        let base_url = base_url.trim_end_matches('/').to_string();
+        let api_key = api_key.into();
+        Self {
+            sdk: LlmSdk::new(&base_url, &api_key, max_retries),
+            base_url,
+            api_key,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiProvider {
+    async fn chat_completion(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<ChatCompletionResponse> {
+        self.sdk.chat_completion(req).await
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        req: ChatCompletionRequest,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<String>>> {
+        stream_chat_completion(
+            &self.client,
+            &format!("{}/chat/completions", self.base_url),
+            Some(&self.api_key),
+            serde_json::to_value(req)?,
+        )
+        .await
+    }
+
+    async fn whisper(&self, req: WhisperRequest) -> anyhow::Result<WhisperResponse> {
+        self.sdk.whisper(req).await
+    }
+
+    async fn speech(&self, req: SpeechRequest) -> anyhow::Result<Bytes> {
+        self.sdk.speech(req).await
+    }
+
+    async fn create_image(&self, req: CreateImageRequest) -> anyhow::Result<CreateImageResponse> {
+        self.sdk.create_image(req).await
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+
+use anyhow::anyhow;
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use serde_json::{json, Value};
+
+/// Send a chat completion request with `stream: true` to an OpenAI compatible
+/// endpoint, and turn the server sent events into a stream of content deltas.
+pub(crate) async fn stream_chat_completion(
+    client: &reqwest::Client,
+    url: &str,
+    api_key: Option<&str>,
+    mut body: Value,
+) -> anyhow::Result<BoxStream<'static, anyhow::Result<String>>> {
+    body["stream"] = json!(true);
+    let mut builder = client.post(url).json(&body);
+    if let Some(api_key) = api_key {
+        builder = builder.bearer_auth(api_key);
+    }
+    let res = builder.send().await?;
+    let status = res.status();
+    if !status.is_success() {
+        let text = res.text().await.unwrap_or_default();
+        return Err(anyhow!("llm returned {}: {}", status, text));
+    }
+
+    let state = StreamState {
+        inner: res.bytes_stream().boxed(),
+        buf: Vec::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+    let stream = futures::stream::unfold(state, |mut s| async move {
+        loop {
+            if let Some(delta) = s.pending.pop_front() {
+                return Some((Ok(delta), s));
+            }
+            if s.done {
+                return None;
+            }
+            match s.inner.next().await {
+                Some(Ok(chunk)) => s.feed(&chunk),
+                Some(Err(e)) => {
+                    s.done = true;
+                    return Some((Err(e.into()), s));
+                }
+                None => return None,
+            }
+        }
+    });
+    Ok(stream.boxed())
+}
+
+struct StreamState {
+    inner: BoxStream<'static, reqwest::Result<Bytes>>,
+    // bytes of an incomplete line, a chunk may split an utf-8 char
+    buf: Vec<u8>,
+    pending: VecDeque<String>,
+    done: bool,
+}
+
+impl StreamState {
+    fn feed(&mut self, chunk: &[u8]) {
+        self.buf.extend_from_slice(chunk);
+        while let Some(pos) = self.buf.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=pos).collect();
+            match parse_line(&String::from_utf8_lossy(&line)) {
+                StreamLine::Delta(v) => self.pending.push_back(v),
+                StreamLine::Done => self.done = true,
+                StreamLine::Skip => {}
+            }
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum StreamLine {
+    Delta(String),
+    Done,
+    Skip,
+}
+
+fn parse_line(line: &str) -> StreamLine {
+    let Some(data) = line.trim().strip_prefix("data:") else {
+        return StreamLine::Skip;
+    };
+    let data = data.trim();
+    if data == "[DONE]" {
+        return StreamLine::Done;
+    }
+    let Ok(value) = serde_json::from_str::<Value>(data) else {
+        return StreamLine::Skip;
+    };
+    match value["choices"][0]["delta"]["content"].as_str() {
+        Some(v) if !v.is_empty() => StreamLine::Delta(v.to_string()),
+        _ => StreamLine::Skip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_should_extract_delta() {
+        let line = r#"data: {"id":"1","choices":[{"index":0,"delta":{"content":"你好"}}]}"#;
+        assert_eq!(parse_line(line), StreamLine::Delta("你好".to_string()));
+        assert_eq!(parse_line("data: [DONE]"), StreamLine::Done);
+        assert_eq!(parse_line(": keep-alive"), StreamLine::Skip);
+        let line = r#"data: {"id":"1","choices":[{"index":0,"delta":{"role":"assistant"}}]}"#;
+        assert_eq!(parse_line(line), StreamLine::Skip);
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fmt, future::Future, sync::Arc, time::Instant};
+
+use dashmap::DashMap;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::{channel::EventChannel, handlers::AssistantStep, DeviceId};
+
+/// Prometheus metrics of the assistant, served on `/metrics`.
+pub(crate) struct Metrics {
+    registry: Registry,
+    steps: IntCounterVec,
+    step_duration: HistogramVec,
+    tool_calls: IntCounterVec,
+    llm_requests: IntCounterVec,
+    llm_errors: IntCounterVec,
+    llm_duration: HistogramVec,
+    sse_connections: IntGauge,
+    online_devices: IntGauge,
+    event_channels: IntGauge,
+    asset_bytes: IntCounterVec,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> anyhow::Result<Self> {
+        let registry = Registry::new_custom(Some("ava".to_string()), None)?;
+        // LLM calls take seconds, up to a minute or two for long answers
+        let buckets = vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 40.0, 80.0];
+        let steps = IntCounterVec::new(
+            Opts::new("steps_total", "Pipeline steps started"),
+            &["step"],
+        )?;
+        let step_duration = HistogramVec::new(
+            HistogramOpts::new("step_duration_seconds", "Time spent in each pipeline step")
+                .buckets(buckets.clone()),
+            &["step"],
+        )?;
+        let tool_calls = IntCounterVec::new(
+            Opts::new("tool_calls_total", "Tools picked by the model"),
+            &["tool"],
+        )?;
+        let llm_requests = IntCounterVec::new(
+            Opts::new("llm_requests_total", "Requests to the LLM providers"),
+            &["capability"],
+        )?;
+        let llm_errors = IntCounterVec::new(
+            Opts::new("llm_errors_total", "Failed requests to the LLM providers"),
+            &["capability"],
+        )?;
+        let llm_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "llm_request_duration_seconds",
+                "Time taken by the LLM providers",
+            )
+            .buckets(buckets),
+            &["capability"],
+        )?;
+        let sse_connections = IntGauge::new("sse_connections", "Connected event streams")?;
+        let online_devices = IntGauge::new("online_devices", "Devices with a client connected")?;
+        let event_channels = IntGauge::new("event_channels", "Devices with an event channel")?;
+        let asset_bytes = IntCounterVec::new(
+            Opts::new("asset_bytes_written_total", "Bytes of generated assets"),
+            &["kind"],
+        )?;
+
+        registry.register(Box::new(steps.clone()))?;
+        registry.register(Box::new(step_duration.clone()))?;
+        registry.register(Box::new(tool_calls.clone()))?;
+        registry.register(Box::new(llm_requests.clone()))?;
+        registry.register(Box::new(llm_errors.clone()))?;
+        registry.register(Box::new(llm_duration.clone()))?;
+        registry.register(Box::new(sse_connections.clone()))?;
+        registry.register(Box::new(online_devices.clone()))?;
+        registry.register(Box::new(event_channels.clone()))?;
+        registry.register(Box::new(asset_bytes.clone()))?;
+        Ok(Self {
+            registry,
+            steps,
+            step_duration,
+            tool_calls,
+            llm_requests,
+            llm_errors,
+            llm_duration,
+            sse_connections,
+            online_devices,
+            event_channels,
+            asset_bytes,
+        })
+    }
+
+    /// Count a step in, its duration is observed when the timer is dropped.
+    pub(crate) fn step(&self, step: AssistantStep) -> HistogramTimer {
+        let step = step.to_string();
+        self.steps.with_label_values(&[&step]).inc();
+        self.step_duration.with_label_values(&[&step]).start_timer()
+    }
+
+    pub(crate) fn tool_called(&self, tool: &str) {
+        self.tool_calls.with_label_values(&[tool]).inc();
+    }
+
+    /// Run a request to the provider of `capability`, counting it in.
+    pub(crate) async fn llm_request<T>(
+        &self,
+        capability: &str,
+        fut: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        let start = Instant::now();
+        let ret = fut.await;
+        self.llm_requests.with_label_values(&[capability]).inc();
+        self.llm_duration
+            .with_label_values(&[capability])
+            .observe(start.elapsed().as_secs_f64());
+        if ret.is_err() {
+            self.llm_error(capability);
+        }
+        ret
+    }
+
+    /// count an error which shows up after the request, e.g. in a stream
+    pub(crate) fn llm_error(&self, capability: &str) {
+        self.llm_errors.with_label_values(&[capability]).inc();
+    }
+
+    pub(crate) fn asset_written(&self, kind: &str, bytes: usize) {
+        self.asset_bytes
+            .with_label_values(&[kind])
+            .inc_by(bytes as u64);
+    }
+
+    /// Render all the metrics in the text format, the connections are taken
+    /// from the presence of the event channels at the time.
+    pub(crate) fn render(
+        &self,
+        events: &DashMap<DeviceId, Arc<EventChannel>>,
+    ) -> anyhow::Result<String> {
+        let connections: Vec<usize> = events.iter().map(|v| v.subscribers()).collect();
+        self.sse_connections
+            .set(connections.iter().sum::<usize>() as i64);
+        self.online_devices
+            .set(connections.iter().filter(|v| **v > 0).count() as i64);
+        self.event_channels.set(events.len() as i64);
+
+        let mut buf = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buf)?;
+        Ok(String::from_utf8(buf)?)
+    }
+}
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn metrics_should_count_steps_and_llm_errors() -> anyhow::Result<()> {
+        let metrics = Metrics::new()?;
+        drop(metrics.step(AssistantStep::Transcrition));
+        metrics.tool_called("draw_image");
+        metrics.llm_request("chat", async { Ok(()) }).await?;
+        let ret = metrics
+            .llm_request::<()>("chat", async { anyhow::bail!("upstream is down") })
+            .await;
+        assert!(ret.is_err());
+        metrics.asset_written("audio", 1024);
+
+        let events = DashMap::new();
+        let channel = Arc::new(EventChannel::after(0));
+        let _sub = channel.subscribe(None);
+        events.insert(DeviceId::new(), channel);
+        events.insert(DeviceId::new(), Arc::new(EventChannel::after(0)));
+        let text: String = metrics.render(&events)?;
+        assert!(text.contains("ava_steps_total{step=\"transcrition\"} 1"));
+        assert!(text.contains("ava_step_duration_seconds_count{step=\"transcrition\"} 1"));
+        assert!(text.contains("ava_tool_calls_total{tool=\"draw_image\"} 1"));
+        assert!(text.contains("ava_llm_requests_total{capability=\"chat\"} 2"));
+        assert!(text.contains("ava_llm_errors_total{capability=\"chat\"} 1"));
+        assert!(text.contains("ava_asset_bytes_written_total{kind=\"audio\"} 1024"));
+        assert!(text.contains("ava_sse_connections 1"));
+        assert!(text.contains("ava_online_devices 1"));
+        assert!(text.contains("ava_event_channels 2"));
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+/// Sentences shorter than this are merged with the next one, so we don't
+/// synthesize a lot of tiny audio segments like "Hi."
+const MIN_SENTENCE_CHARS: usize = 16;
+
+/// Split streamed text into sentences as soon as they are complete.
+#[derive(Debug, Default)]
+pub(crate) struct SentenceSplitter {
+    buf: String,
+}
+
+impl SentenceSplitter {
+    /// feed a piece of text, returns the sentences completed by it
+    pub(crate) fn push(&mut self, delta: &str) -> Vec<String> {
+        self.buf.push_str(delta);
+        let mut sentences = vec![];
+        while let Some(end) = self.find_boundary() {
+            let sentence: String = self.buf.drain(..end).collect();
+            let sentence = sentence.trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence.to_string());
+            }
+        }
+        sentences
+    }
+
+    /// the remaining text once the stream ends
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buf);
+        let rest = rest.trim();
+        (!rest.is_empty()).then(|| rest.to_string())
+    }
+
+    fn find_boundary(&self) -> Option<usize> {
+        for (i, c) in self.buf.char_indices() {
+            if !is_terminator(c) {
+                continue;
+            }
+            let end = i + c.len_utf8();
+            // ascii punctuations shall be followed by a space, e.g. "3.14" is not a boundary
+            if c.is_ascii_punctuation() {
+                match self.buf[end..].chars().next() {
+                    Some(next) if next.is_whitespace() => {}
+                    _ => continue,
+                }
+            }
+            if self.buf[..end].trim().chars().count() >= MIN_SENTENCE_CHARS {
+                return Some(end);
+            }
+        }
+        None
+    }
+}
+
+fn is_terminator(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | ';' | '\n' | '。' | '！' | '？' | '；')
+}
+
+pub(crate) fn split_sentences(text: &str) -> Vec<String> {
+    let mut splitter = SentenceSplitter::default();
+    let mut sentences = splitter.push(text);
+    sentences.extend(splitter.finish());
+    sentences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splitter_should_emit_sentences_as_they_complete() {
+        let mut splitter = SentenceSplitter::default();
+        assert!(splitter.push("Pi is about 3.").is_empty());
+        assert!(splitter.push("14, as you may know").is_empty());
+        assert_eq!(
+            splitter.push(". Short. And now"),
+            ["Pi is about 3.14, as you may know."]
+        );
+        assert_eq!(
+            splitter.push(" something else! "),
+            ["Short. And now something else!"]
+        );
+        assert_eq!(splitter.finish(), None);
+    }
+
+    #[test]
+    fn split_sentences_should_support_chinese() {
+        let text = "今天天气很好，我们一起去公园散步吧。好的，我马上就来，请等我一下哦！最后一句";
+        assert_eq!(
+            split_sentences(text),
+            [
+                "今天天气很好，我们一起去公园散步吧。",
+                "好的，我马上就来，请等我一下哦！",
+                "最后一句"
+            ]
+        );
+    }
+}
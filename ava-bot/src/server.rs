@@ -0,0 +1,153 @@
+use std::{
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use axum::Router;
+use axum_server::{tls_rustls::RustlsConfig, Handle};
+use clap::ValueEnum;
+use serde::Deserialize;
+use tokio::signal;
+use tracing::{info, warn};
+
+use crate::{config::ServerConfig, AppState};
+
+/// names the self-signed cert is valid for
+const SELF_SIGNED_NAMES: [&str; 3] = ["localhost", "127.0.0.1", "::1"];
+/// time left for the connections to close once requests are drained
+const CONNECTION_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ServeMode {
+    /// Plain HTTP, e.g. behind a reverse proxy terminating TLS
+    Http,
+    /// TLS with cert.pem and key.pem under cert_path, reloaded when they change
+    Tls,
+    /// TLS with a self-signed cert generated at startup, for development
+    SelfSigned,
+}
+
+impl ServeMode {
+    /// scheme of requests coming directly to the server
+    pub fn scheme(&self) -> &'static str {
+        match self {
+            ServeMode::Http => "http",
+            ServeMode::Tls | ServeMode::SelfSigned => "https",
+        }
+    }
+}
+
+/// Serve the app in the configured mode until shut down by `handle`.
+pub async fn serve(config: &ServerConfig, app: Router, handle: Handle) -> anyhow::Result<()> {
+    let addr = SocketAddr::new(config.host, config.port);
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+    info!("Listening on {} ({:?})", addr, config.mode);
+    match config.mode {
+        ServeMode::Http => {
+            axum_server::bind(addr)
+                .handle(handle)
+                .serve(service)
+                .await?
+        }
+        ServeMode::Tls => {
+            let (cert, key) = cert_files(&config.cert_path);
+            let tls = RustlsConfig::from_pem_file(&cert, &key)
+                .await
+                .with_context(|| format!("failed to load cert from {}", cert.display()))?;
+            if config.cert_reload_interval > 0 {
+                let interval = Duration::from_secs(config.cert_reload_interval);
+                tokio::spawn(reload_certs(tls.clone(), cert, key, interval));
+            }
+            axum_server::bind_rustls(addr, tls)
+                .handle(handle)
+                .serve(service)
+                .await?
+        }
+        ServeMode::SelfSigned => {
+            warn!("serving with a self-signed cert, browsers will warn about it");
+            let tls = self_signed_config().await?;
+            axum_server::bind_rustls(addr, tls)
+                .handle(handle)
+                .serve(service)
+                .await?
+        }
+    }
+    Ok(())
+}
+
+/// Wait for SIGINT or SIGTERM, then drain the running requests and stop the
+/// server.
+pub async fn shutdown_on_signal(state: Arc<AppState>, handle: Handle, timeout: Duration) {
+    shutdown_signal().await;
+    state.shutdown(timeout).await;
+    handle.graceful_shutdown(Some(CONNECTION_GRACE_PERIOD));
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install ctrl-c handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+pub(crate) fn cert_files(cert_path: &Path) -> (PathBuf, PathBuf) {
+    (cert_path.join("cert.pem"), cert_path.join("key.pem"))
+}
+
+async fn self_signed_config() -> anyhow::Result<RustlsConfig> {
+    let names: Vec<String> = SELF_SIGNED_NAMES.iter().map(|v| v.to_string()).collect();
+    let cert = rcgen::generate_simple_self_signed(names)?;
+    let config = RustlsConfig::from_pem(
+        cert.serialize_pem()?.into_bytes(),
+        cert.serialize_private_key_pem().into_bytes(),
+    )
+    .await?;
+    Ok(config)
+}
+
+/// Poll the pem files and reload them once both are updated, so renewed certs
+/// are picked up without a restart.
+async fn reload_certs(config: RustlsConfig, cert: PathBuf, key: PathBuf, interval: Duration) {
+    let mut last = modified(&cert, &key);
+    loop {
+        tokio::time::sleep(interval).await;
+        let current = modified(&cert, &key);
+        if current.is_none() || current == last {
+            continue;
+        }
+        match config.reload_from_pem_file(&cert, &key).await {
+            Ok(_) => {
+                info!("reloaded cert from {}", cert.display());
+                last = current;
+            }
+            // the files may be half written, try again next time
+            Err(e) => warn!("failed to reload cert from {}: {}", cert.display(), e),
+        }
+    }
+}
+
+fn modified(cert: &Path, key: &Path) -> Option<(SystemTime, SystemTime)> {
+    let cert = fs::metadata(cert).and_then(|m| m.modified()).ok()?;
+    let key = fs::metadata(key).and_then(|m| m.modified()).ok()?;
+    Some((cert, key))
+}
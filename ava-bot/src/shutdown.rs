@@ -0,0 +1,98 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::{handlers::SignalEvent, AppState};
+
+/// Tracks the running assistant pipelines, so shutdown could wait for them.
+#[derive(Debug, Default)]
+pub(crate) struct InFlight {
+    closing: AtomicBool,
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Keeps a pipeline counted as running until dropped.
+pub(crate) struct InFlightGuard<'a>(&'a InFlight);
+
+impl InFlight {
+    /// Count a new pipeline in, None once shutting down.
+    pub(crate) fn enter(&self) -> Option<InFlightGuard<'_>> {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        if self.closing.load(Ordering::SeqCst) {
+            self.leave();
+            return None;
+        }
+        Some(InFlightGuard(self))
+    }
+
+    fn leave(&self) {
+        if self.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.idle.notify_waiters();
+        }
+    }
+
+    fn close(&self) {
+        self.closing.store(true, Ordering::SeqCst);
+    }
+
+    /// wait until no pipeline is running
+    async fn drained(&self) {
+        loop {
+            // created before checking so a notification in between isn't lost
+            let idle = self.idle.notified();
+            if self.count.load(Ordering::SeqCst) == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.leave();
+    }
+}
+
+impl AppState {
+    /// Stop taking new requests, wait for the running ones to finish (at most
+    /// `timeout`) and tell every connected client the server is going away,
+    /// which also ends their event streams.
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.in_flight.close();
+        info!("shutting down, waiting for running requests");
+        if tokio::time::timeout(timeout, self.in_flight.drained())
+            .await
+            .is_err()
+        {
+            warn!("requests still running after {:?}, stop anyway", timeout);
+        }
+        for channel in self.events.iter() {
+            channel.send(SignalEvent::Shutdown.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn in_flight_should_drain_and_reject_after_close() {
+        let in_flight = InFlight::default();
+        let guard = in_flight.enter().unwrap();
+        in_flight.close();
+        assert!(in_flight.enter().is_none());
+
+        let drained = in_flight.drained();
+        drop(guard);
+        tokio::time::timeout(Duration::from_secs(1), drained)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+mod sled_store;
+
+use std::fmt::Debug;
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{handlers::ChatReplyData, DeviceId};
+
+pub(crate) use sled_store::SledHistoryStore;
+
+/// Persisted conversation history, keyed by device_id.
+pub(crate) trait HistoryStore: Debug + Send + Sync {
+    /// record a finished turn
+    fn save_turn(&self, turn: &ChatTurn) -> anyhow::Result<()>;
+    /// latest `limit` turns of the device, oldest first
+    fn list_turns(&self, device_id: DeviceId, limit: usize) -> anyhow::Result<Vec<ChatTurn>>;
+    fn delete_turn(&self, turn: &ChatTurn) -> anyhow::Result<()>;
+    /// delete the turns of all devices created before `time`, returns how many
+    fn delete_turns_before(&self, time: DateTime<Utc>) -> anyhow::Result<usize>;
+}
+
+/// One exchange between the user and Ava.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ChatTurn {
+    pub(crate) id: String,
+    pub(crate) device_id: DeviceId,
+    /// transcript of user's input
+    pub(crate) input: String,
+    /// final replies, carrying the urls of the generated audio / images
+    pub(crate) replies: Vec<ChatReplyData>,
+    pub(crate) created_at: DateTime<Utc>,
+}
+
+impl ChatTurn {
+    pub(crate) fn new(
+        id: impl Into<String>,
+        device_id: DeviceId,
+        input: impl Into<String>,
+        replies: Vec<ChatReplyData>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            device_id,
+            input: input.into(),
+            replies,
+            created_at: Utc::now(),
+        }
+    }
+
+    /// urls of the audio and images the turn refers to
+    pub(crate) fn asset_urls(&self) -> impl Iterator<Item = &str> {
+        self.replies.iter().flat_map(|v| v.asset_urls())
+    }
+
+    pub(crate) fn datetime(&self) -> String {
+        self.created_at
+            .with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    }
+}
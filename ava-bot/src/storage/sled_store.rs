@@ -0,0 +1,135 @@
+use chrono::{DateTime, Utc};
+
+use crate::DeviceId;
+
+use super::{ChatTurn, HistoryStore};
+
+#[derive(Debug)]
+pub(crate) struct SledHistoryStore {
+    db: sled::Db,
+}
+
+impl SledHistoryStore {
+    /// turns are kept in the default tree, accounts use the other trees of
+    /// the database
+    pub(crate) fn new(db: sled::Db) -> Self {
+        Self { db }
+    }
+
+    /// A store which is removed once dropped, for testing purpose.
+    #[allow(dead_code)]
+    pub(crate) fn temporary() -> anyhow::Result<Self> {
+        Ok(Self {
+            db: sled::Config::new().temporary(true).open()?,
+        })
+    }
+}
+
+impl HistoryStore for SledHistoryStore {
+    fn save_turn(&self, turn: &ChatTurn) -> anyhow::Result<()> {
+        let key = turn_key(turn.device_id, turn.created_at.timestamp_micros(), &turn.id);
+        self.db.insert(key, serde_json::to_vec(turn)?)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn list_turns(&self, device_id: DeviceId, limit: usize) -> anyhow::Result<Vec<ChatTurn>> {
+        let mut turns = self
+            .db
+            .scan_prefix(device_prefix(device_id))
+            .rev()
+            .take(limit)
+            .map(|item| {
+                let (_, value) = item?;
+                Ok(serde_json::from_slice(&value)?)
+            })
+            .collect::<anyhow::Result<Vec<ChatTurn>>>()?;
+        turns.reverse();
+        Ok(turns)
+    }
+
+    fn delete_turn(&self, turn: &ChatTurn) -> anyhow::Result<()> {
+        let key = turn_key(turn.device_id, turn.created_at.timestamp_micros(), &turn.id);
+        self.db.remove(key)?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn delete_turns_before(&self, time: DateTime<Utc>) -> anyhow::Result<usize> {
+        let ts = time.timestamp_micros();
+        let mut count = 0;
+        for item in self.db.iter() {
+            let (key, _) = item?;
+            if key_timestamp(&key).is_some_and(|v| v < ts) {
+                self.db.remove(key)?;
+                count += 1;
+            }
+        }
+        self.db.flush()?;
+        Ok(count)
+    }
+}
+
+fn device_prefix(device_id: DeviceId) -> Vec<u8> {
+    let mut key = device_id.to_string().into_bytes();
+    key.push(0);
+    key
+}
+
+// keys are sorted by device_id and then by time
+fn turn_key(device_id: DeviceId, ts: i64, id: &str) -> Vec<u8> {
+    let mut key = device_prefix(device_id);
+    key.extend_from_slice(&ts.to_be_bytes());
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+fn key_timestamp(key: &[u8]) -> Option<i64> {
+    let start = key.iter().position(|v| *v == 0)? + 1;
+    let bytes = key.get(start..start + 8)?;
+    Some(i64::from_be_bytes(bytes.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::SpeechResult;
+
+    #[test]
+    fn sled_store_should_list_turns_of_device_in_order() -> anyhow::Result<()> {
+        let store = SledHistoryStore::temporary()?;
+        let (a, b) = (DeviceId::new(), DeviceId::new());
+        for i in 0..3 {
+            let urls = vec![format!("/assets/audio/{}/1.mp3", a)];
+            let reply = SpeechResult::new(format!("reply {}", i), urls);
+            store.save_turn(&ChatTurn::new(i.to_string(), a, "hi", vec![reply.into()]))?;
+        }
+        store.save_turn(&ChatTurn::new("x", b, "hello", vec![]))?;
+
+        let turns = store.list_turns(a, 2)?;
+        let ids: Vec<_> = turns.iter().map(|t| t.id.as_str()).collect();
+        assert_eq!(ids, ["1", "2"]);
+        assert_eq!(store.list_turns(b, 10)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn sled_store_should_delete_old_turns() -> anyhow::Result<()> {
+        let store = SledHistoryStore::temporary()?;
+        let a = DeviceId::new();
+        let mut old = ChatTurn::new("old", a, "hi", vec![]);
+        old.created_at = Utc::now() - chrono::Duration::days(10);
+        store.save_turn(&old)?;
+        store.save_turn(&ChatTurn::new("new", a, "hello", vec![]))?;
+
+        let count = store.delete_turns_before(Utc::now() - chrono::Duration::days(1))?;
+        assert_eq!(count, 1);
+        let turns = store.list_turns(a, 10)?;
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].id, "new");
+
+        store.delete_turn(&turns[0])?;
+        assert!(store.list_turns(a, 10)?.is_empty());
+        Ok(())
+    }
+}
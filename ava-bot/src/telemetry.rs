@@ -0,0 +1,198 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{File, OpenOptions},
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use opentelemetry::{
+    global,
+    trace::{TraceError, TracerProvider as _},
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    export::trace::{ExportResult, SpanData, SpanExporter},
+    runtime,
+    trace::{self, Tracer, TracerProvider},
+    Resource,
+};
+use serde::Serialize;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+use crate::Args;
+
+const SERVICE_NAME: &str = "ava-bot";
+
+/// Flushes the spans not exported yet when dropped, keep it until exit.
+#[must_use]
+pub struct TracingGuard {
+    exporting: bool,
+}
+
+/// Writes each span as a line of json, for checking the traces without a
+/// collector.
+struct FileExporter {
+    writer: BufWriter<File>,
+}
+
+#[derive(Debug, Serialize)]
+struct SpanLine {
+    trace_id: String,
+    span_id: String,
+    parent_span_id: String,
+    name: String,
+    start: DateTime<Utc>,
+    duration_ms: f64,
+    attributes: BTreeMap<String, String>,
+}
+
+/// Install the tracing subscriber. Logs go to stdout, filtered by `RUST_LOG`.
+/// Spans are also exported to the OTLP collector or the trace file given by
+/// args.
+pub fn init_tracing(args: &Args) -> anyhow::Result<TracingGuard> {
+    let tracer = match (&args.otlp_endpoint, &args.trace_file) {
+        (Some(endpoint), _) => Some(otlp_tracer(endpoint)?),
+        (None, Some(path)) => Some(file_tracer(path)?),
+        (None, None) => None,
+    };
+    let exporting = tracer.is_some();
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(tracer.map(|v| tracing_opentelemetry::layer().with_tracer(v)))
+        .try_init()?;
+    Ok(TracingGuard { exporting })
+}
+
+fn otlp_tracer(endpoint: &str) -> anyhow::Result<Tracer> {
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(trace_config())
+        .install_batch(runtime::Tokio)
+        .with_context(|| format!("failed to set up the otlp exporter to {}", endpoint))?;
+    Ok(tracer)
+}
+
+fn file_tracer(path: &Path) -> anyhow::Result<Tracer> {
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(FileExporter::new(path)?)
+        .with_config(trace_config())
+        .build();
+    let tracer = provider.tracer(SERVICE_NAME);
+    global::set_tracer_provider(provider);
+    Ok(tracer)
+}
+
+fn trace_config() -> trace::Config {
+    trace::config().with_resource(Resource::new([KeyValue::new("service.name", SERVICE_NAME)]))
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        if self.exporting {
+            global::shutdown_tracer_provider();
+        }
+    }
+}
+
+impl FileExporter {
+    fn new(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open trace file {}", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    fn write(&mut self, batch: Vec<SpanData>) -> std::io::Result<()> {
+        for span in batch {
+            serde_json::to_writer(&mut self.writer, &SpanLine::from(span))?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()
+    }
+}
+
+impl SpanExporter for FileExporter {
+    fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+        let ret = self
+            .write(batch)
+            .map_err(|e| TraceError::from(format!("failed to write spans: {}", e)));
+        Box::pin(std::future::ready(ret))
+    }
+}
+
+impl fmt::Debug for FileExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FileExporter").finish_non_exhaustive()
+    }
+}
+
+impl From<SpanData> for SpanLine {
+    fn from(span: SpanData) -> Self {
+        let duration = span
+            .end_time
+            .duration_since(span.start_time)
+            .unwrap_or_default();
+        Self {
+            trace_id: span.span_context.trace_id().to_string(),
+            span_id: span.span_context.span_id().to_string(),
+            parent_span_id: span.parent_span_id.to_string(),
+            name: span.name.to_string(),
+            start: span.start_time.into(),
+            duration_ms: duration.as_secs_f64() * 1000.0,
+            attributes: span
+                .attributes
+                .into_iter()
+                .map(|kv| (kv.key.to_string(), kv.value.to_string()))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::info_span;
+
+    #[test]
+    fn file_exporter_should_write_spans_with_their_fields() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("ava-trace-{}.jsonl", uuid::Uuid::new_v4()));
+        let provider = TracerProvider::builder()
+            .with_simple_exporter(FileExporter::new(&path)?)
+            .build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME));
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let _turn = info_span!("turn", turn_id = "t1", device_id = "d1").entered();
+            let _step = info_span!("transcript").entered();
+        });
+        drop(provider);
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        // children end first
+        assert_eq!(lines[0]["name"], "transcript");
+        assert_eq!(lines[1]["name"], "turn");
+        assert_eq!(lines[1]["attributes"]["turn_id"], "t1");
+        assert_eq!(lines[1]["attributes"]["device_id"], "d1");
+        assert_eq!(lines[0]["parent_span_id"], lines[1]["span_id"]);
+        assert_eq!(lines[0]["trace_id"], lines[1]["trace_id"]);
+        Ok(())
+    }
+}
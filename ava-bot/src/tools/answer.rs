@@ -0,0 +1,60 @@
+use axum::async_trait;
+use futures::channel::mpsc;
+use llm_sdk::chat_completion::ChatCompletionMessage;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::handlers::{chat_completion, in_chat_completion, speak, AssistantStep, SpeechResult};
+
+use super::{AssistantTool, ToolContext, ToolOutput};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub(crate) struct AnswerArgs {
+    /// question or prompt from user
+    pub(crate) prompt: String,
+}
+
+/// Answer with text and speech
+pub(crate) struct Answer;
+
+#[async_trait]
+impl AssistantTool for Answer {
+    type Args = AnswerArgs;
+
+    fn name(&self) -> &'static str {
+        "answer"
+    }
+
+    fn description(&self) -> &'static str {
+        "Just reply based on the prompt."
+    }
+
+    async fn execute(&self, ctx: &ToolContext<'_>, args: AnswerArgs) -> anyhow::Result<ToolOutput> {
+        ctx.event_sender.send(in_chat_completion());
+        let _timer = ctx.metrics.step(AssistantStep::ChatCompletion);
+
+        let assistant = &ctx.config.assistant;
+        let mut messages = vec![ChatCompletionMessage::new_system(
+            &assistant.answer_prompt,
+            &assistant.name,
+        )];
+        messages.extend(ctx.history.iter().cloned());
+        messages.push(ChatCompletionMessage::new_user(args.prompt, ""));
+
+        // speak the answer sentence by sentence while it's being generated
+        let (tx, rx) = mpsc::unbounded();
+        let (output, urls) = tokio::try_join!(
+            chat_completion(
+                ctx.llm.chat.as_ref(),
+                assistant.chat_model,
+                messages,
+                ctx.event_sender,
+                ctx.reply_id,
+                Some(tx),
+                ctx.meter
+            ),
+            speak(ctx, rx),
+        )?;
+        Ok(ToolOutput::new(SpeechResult::new(&output, urls), output))
+    }
+}
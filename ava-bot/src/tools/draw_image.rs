@@ -0,0 +1,76 @@
+use anyhow::anyhow;
+use axum::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use llm_sdk::create_image::{CreateImageRequestBuilder, ImageResponseFormat};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    assets::image_key,
+    error::AssistantError,
+    handlers::{in_draw_image, AssistantStep},
+};
+
+use super::{AssistantTool, DrawImageResult, ToolContext, ToolOutput};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub(crate) struct DrawImageArgs {
+    /// The revised prompt for creating the image
+    pub(crate) prompt: String,
+}
+
+/// Draw a picture based on user's input
+pub(crate) struct DrawImage;
+
+#[async_trait]
+impl AssistantTool for DrawImage {
+    type Args = DrawImageArgs;
+
+    fn name(&self) -> &'static str {
+        "draw_image"
+    }
+
+    fn description(&self) -> &'static str {
+        "Draw an image based on the prompt"
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ToolContext<'_>,
+        args: DrawImageArgs,
+    ) -> anyhow::Result<ToolOutput> {
+        ctx.event_sender.send(in_draw_image());
+        let _timer = ctx.metrics.step(AssistantStep::DrawImage);
+        ctx.send_reply(DrawImageResult::new("", &args.prompt));
+
+        let req = CreateImageRequestBuilder::default()
+            .prompt(args.prompt)
+            .response_format(ImageResponseFormat::B64Json)
+            .build()
+            .unwrap();
+
+        let mut ret = ctx.llm.image.create_image(req).await?;
+        ctx.meter.image();
+        let img = ret
+            .data
+            .pop()
+            .ok_or_else(|| anyhow!("expect at least one data"))?;
+        // compatible providers may ignore the format asked for and give a url
+        let b64_json = img
+            .b64_json
+            .ok_or_else(|| anyhow!("image provider returned no b64_json"))?;
+        let data = STANDARD.decode(b64_json)?;
+        let uuid = Uuid::new_v4().to_string();
+        let key = image_key(ctx.device_id, &uuid);
+        ctx.metrics.asset_written("image", data.len());
+        ctx.blobs
+            .put(&key, data.into(), "image/png")
+            .await
+            .map_err(AssistantError::Storage)?;
+
+        let ret = DrawImageResult::new(ctx.blobs.url(&key), img.revised_prompt);
+        let result = format!("Image drawn with prompt: {}", ret.prompt);
+        Ok(ToolOutput::new(ret, result))
+    }
+}
@@ -1,27 +1,89 @@
+mod answer;
+mod draw_image;
+mod write_code;
+
+use std::fmt;
+
 use askama::Template;
+use axum::async_trait;
 use derive_more::From;
-use llm_sdk::chat_completion::{ChatCompletionMessage, ChatCompletionRequest, Tool};
+use llm_sdk::chat_completion::{
+    ChatCompletionMessage, ChatCompletionRequest, ChatCompletionRequestBuilder, Tool,
+};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
-use strum::{Display, EnumString};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    assets::BlobStore,
+    channel::EventChannel,
+    config::{AppConfig, AssistantConfig},
+    handlers::{ChatReplyData, ChatReplyEvent},
+    limits::Meter,
+    llm::LlmProviders,
+    metrics::Metrics,
+    DeviceId,
+};
+
+use answer::Answer;
+use draw_image::DrawImage;
+use write_code::WriteCode;
 
-#[derive(Debug, Clone, PartialEq, Eq, EnumString, Display, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-#[strum(serialize_all = "snake_case")]
-pub(crate) enum AssistantTool {
-    /// Draw a picture based on user's input
-    DrawImage,
-    /// Write code based on user's input
-    WriteCode,
+/// A tool the model could call. The json schema of `Args` is generated and
+/// sent to the model, the arguments it replies are parsed into `Args`.
+#[async_trait]
+pub(crate) trait AssistantTool: Send + Sync + 'static {
+    type Args: DeserializeOwned + JsonSchema + Send;
 
-    /// Answer
-    Answer,
+    fn name(&self) -> &'static str;
+
+    fn description(&self) -> &'static str;
+
+    /// Run the tool. Progress could be pushed to `ctx.reply_id` while running,
+    /// the final reply is rendered by the caller.
+    async fn execute(&self, ctx: &ToolContext<'_>, args: Self::Args) -> anyhow::Result<ToolOutput>;
 }
 
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
-pub(crate) struct WriteCodeArgs {
-    /// The revised prompt for creating the code
-    pub(crate) prompt: String,
+/// What a tool needs to know about the turn it runs in.
+pub(crate) struct ToolContext<'a> {
+    pub(crate) config: &'a AppConfig,
+    pub(crate) llm: &'a LlmProviders,
+    pub(crate) blobs: &'a dyn BlobStore,
+    // charges the calls to the budget of the user
+    pub(crate) meter: &'a Meter<'a>,
+    pub(crate) metrics: &'a Metrics,
+    pub(crate) device_id: DeviceId,
+    // conversation before this turn
+    pub(crate) history: &'a [ChatCompletionMessage],
+    pub(crate) event_sender: &'a EventChannel,
+    // the reply block of this tool call
+    pub(crate) reply_id: &'a str,
+}
+
+#[derive(Debug)]
+pub(crate) struct ToolOutput {
+    /// rendered as the reply of the tool call
+    pub(crate) reply: ChatReplyData,
+    /// fed back to the model
+    pub(crate) result: String,
+}
+
+/// All the tools available to the model.
+#[derive(Default)]
+pub(crate) struct ToolRegistry {
+    tools: Vec<Box<dyn DynTool>>,
+}
+
+// object safe version of AssistantTool, to keep tools of different args together
+#[async_trait]
+trait DynTool: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn definition(&self) -> Tool;
+
+    /// whether the arguments are the ones the tool takes
+    fn check(&self, arguments: &str) -> Result<(), serde_json::Error>;
+
+    async fn call(&self, ctx: &ToolContext<'_>, arguments: &str) -> anyhow::Result<ToolOutput>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Template)]
@@ -39,18 +101,6 @@ impl WriteCodeResult {
     }
 }
 
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
-pub(crate) struct DrawImageArgs {
-    /// The revised prompt for creating the image
-    pub(crate) prompt: String,
-}
-
-#[derive(Debug, Clone, Deserialize, JsonSchema)]
-pub(crate) struct AnswerArgs {
-    /// question or prompt from user
-    pub(crate) prompt: String,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize, Template, From)]
 #[template(path = "blocks/image.html.j2")]
 pub(crate) struct DrawImageResult {
@@ -81,25 +131,132 @@ impl From<WriteCodeResult> for String {
     }
 }
 
+impl ToolContext<'_> {
+    /// update the reply block of this tool call
+    pub(crate) fn send_reply(&self, data: impl Into<ChatReplyData>) {
+        self.event_sender
+            .send(ChatReplyEvent::new(self.reply_id, data).into());
+    }
+}
+
+impl ToolOutput {
+    pub(crate) fn new(reply: impl Into<ChatReplyData>, result: impl Into<String>) -> Self {
+        Self {
+            reply: reply.into(),
+            result: result.into(),
+        }
+    }
+}
+
+impl ToolRegistry {
+    /// registry with the builtin tools
+    pub(crate) fn new() -> Self {
+        let mut registry = Self::default();
+        registry.register(DrawImage);
+        registry.register(WriteCode);
+        registry.register(Answer);
+        registry
+    }
+
+    /// register a tool, it replaces the tool with the same name if any
+    pub(crate) fn register(&mut self, tool: impl AssistantTool) {
+        self.tools.retain(|v| v.name() != tool.name());
+        self.tools.push(Box::new(tool));
+    }
+
+    pub(crate) fn definitions(&self) -> Vec<Tool> {
+        self.tools.iter().map(|v| v.definition()).collect()
+    }
+
+    /// Why the model can't call the tool with the arguments, if it can't. It's
+    /// told to the model, so it could correct the call.
+    pub(crate) fn check(&self, name: &str, arguments: &str) -> Result<(), String> {
+        let Some(tool) = self.tools.iter().find(|v| v.name() == name) else {
+            return Err(format!("no tool named `{}`", name));
+        };
+        tool.check(arguments)
+            .map_err(|e| format!("invalid arguments of `{}`: {}", name, e))
+    }
+
+    pub(crate) async fn call(
+        &self,
+        ctx: &ToolContext<'_>,
+        name: &str,
+        arguments: &str,
+    ) -> anyhow::Result<ToolOutput> {
+        let Some(tool) = self.tools.iter().find(|v| v.name() == name) else {
+            anyhow::bail!("no proper tool found");
+        };
+        ctx.metrics.tool_called(tool.name());
+        tool.call(ctx, arguments).await
+    }
+}
+
+impl fmt::Debug for ToolRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.tools.iter().map(|v| v.name()))
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<T: AssistantTool> DynTool for T {
+    fn name(&self) -> &'static str {
+        AssistantTool::name(self)
+    }
+
+    fn definition(&self) -> Tool {
+        Tool::new_function::<T::Args>(AssistantTool::name(self), self.description())
+    }
+
+    fn check(&self, arguments: &str) -> Result<(), serde_json::Error> {
+        serde_json::from_str::<T::Args>(arguments).map(|_| ())
+    }
+
+    async fn call(&self, ctx: &ToolContext<'_>, arguments: &str) -> anyhow::Result<ToolOutput> {
+        let args = serde_json::from_str(arguments)?;
+        self.execute(ctx, args).await
+    }
+}
+
 pub(crate) fn tool_completion_request(
-    input: impl Into<String>,
-    name: &str,
-) -> ChatCompletionRequest {
-    let messages = vec![
-        ChatCompletionMessage::new_system(
-            "I can help to identify which tool to use, if no proper tool could be used, I'll directly reply the message with pure text",
-             "Ava"),
-        ChatCompletionMessage::new_user(input.into(), name),
-    ];
-
-    ChatCompletionRequest::new_with_tools(messages, all_tools())
-}
-
-// TODO: llm-sdk shall provide functionality to generate this code
-fn all_tools() -> Vec<Tool> {
-    vec![
-        Tool::new_function::<DrawImageArgs>("draw_image", "Draw an image based on the prompt"),
-        Tool::new_function::<WriteCodeArgs>("write_code", "Write code based on the prompt"),
-        Tool::new_function::<AnswerArgs>("answer", "Just reply based on the prompt."),
-    ]
+    config: &AssistantConfig,
+    conversation: Vec<ChatCompletionMessage>,
+    tools: Vec<Tool>,
+) -> anyhow::Result<ChatCompletionRequest> {
+    let mut messages = vec![ChatCompletionMessage::new_system(
+        &config.tools_prompt,
+        &config.name,
+    )];
+    messages.extend(conversation);
+
+    let req = ChatCompletionRequestBuilder::default()
+        .model(config.tools_model)
+        .messages(messages)
+        .tools(tools)
+        .build()?;
+    Ok(req)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_should_generate_builtin_tool_definitions() {
+        let registry = ToolRegistry::new();
+        let tools = serde_json::to_value(registry.definitions()).unwrap();
+        let names: Vec<_> = tools
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["function"]["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["draw_image", "write_code", "answer"]);
+        assert_eq!(
+            tools[0]["function"]["parameters"]["properties"]["prompt"]["type"],
+            "string"
+        );
+    }
 }
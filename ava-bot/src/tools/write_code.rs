@@ -0,0 +1,61 @@
+use axum::async_trait;
+use comrak::markdown_to_html;
+use llm_sdk::chat_completion::ChatCompletionMessage;
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+use crate::handlers::{chat_completion, in_write_code, AssistantStep};
+
+use super::{AssistantTool, ToolContext, ToolOutput, WriteCodeResult};
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+pub(crate) struct WriteCodeArgs {
+    /// The revised prompt for creating the code
+    pub(crate) prompt: String,
+}
+
+/// Write code based on user's input
+pub(crate) struct WriteCode;
+
+#[async_trait]
+impl AssistantTool for WriteCode {
+    type Args = WriteCodeArgs;
+
+    fn name(&self) -> &'static str {
+        "write_code"
+    }
+
+    fn description(&self) -> &'static str {
+        "Write code based on the prompt"
+    }
+
+    async fn execute(
+        &self,
+        ctx: &ToolContext<'_>,
+        args: WriteCodeArgs,
+    ) -> anyhow::Result<ToolOutput> {
+        ctx.event_sender.send(in_write_code());
+        let _timer = ctx.metrics.step(AssistantStep::WriteCode);
+
+        let assistant = &ctx.config.assistant;
+        let mut messages = vec![ChatCompletionMessage::new_system(
+            &assistant.write_code_prompt,
+            &assistant.name,
+        )];
+        messages.extend(ctx.history.iter().cloned());
+        messages.push(ChatCompletionMessage::new_user(args.prompt, ""));
+
+        let md = chat_completion(
+            ctx.llm.chat.as_ref(),
+            assistant.chat_model,
+            messages,
+            ctx.event_sender,
+            ctx.reply_id,
+            None,
+            ctx.meter,
+        )
+        .await?;
+        let content = markdown_to_html(&md, &comrak::ComrakOptions::default());
+        Ok(ToolOutput::new(WriteCodeResult::new(content), md))
+    }
+}
@@ -0,0 +1,1149 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use ava_bot::{
+    llm::{LlmProviders, MockProvider},
+    router, AppConfig, AppState, DeviceId,
+};
+use axum::{
+    body::{Body, BoxBody, HttpBody},
+    extract::ConnectInfo,
+    http::{header, request, Request, Response, StatusCode},
+    Router,
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{net::TcpStream, time::timeout};
+use tokio_tungstenite::{
+    tungstenite::{client::IntoClientRequest, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+use tower::ServiceExt;
+use uuid::Uuid;
+
+const BOUNDARY: &str = "ava-test-boundary";
+const ADMIN_TOKEN: &str = "ava-test-admin";
+
+#[tokio::test]
+async fn answer_tool_should_reply_with_text_and_speech() -> Result<()> {
+    let app = TestApp::new(MockProvider::default().with_reply("Hi, nice to meet you"))?;
+    let mut events = app.connect_events().await?;
+
+    let res = app.post_audio(b"fake audio").await?;
+    assert_eq!(res, json!({"status": "done"}));
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(
+        labels(&events),
+        [
+            "processing:upload_audio",
+            "processing:transcrition",
+            "input_skeleton",
+            "input",
+            "processing:thinking",
+            "reply_skeleton",
+            "processing:chat_completion",
+            "reply_delta",
+            "speech_segment",
+            "complete",
+            "reply",
+            "processing:thinking",
+            "complete",
+        ]
+    );
+    assert!(events[3].data.contains("Hello Ava"));
+    assert_eq!(events[7].data, "Hi, nice to meet you");
+    assert!(events[8].data.starts_with("/assets/audio/"));
+    assert!(events[10].data.contains("Hi, nice to meet you"));
+    assert!(events[10].data.contains("speech-player"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn draw_image_tool_should_reply_with_image() -> Result<()> {
+    let provider = MockProvider::new("draw a cat")
+        .with_tool_call("draw_image", json!({"prompt": "a cute cat"}));
+    let app = TestApp::new(provider)?;
+    let mut events = app.connect_events().await?;
+
+    app.post_audio(b"fake audio").await?;
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(
+        labels(&events),
+        [
+            "processing:upload_audio",
+            "processing:transcrition",
+            "input_skeleton",
+            "input",
+            "processing:thinking",
+            "reply_skeleton",
+            "processing:draw_image",
+            "reply",
+            "complete",
+            "reply",
+            "processing:thinking",
+            "complete",
+        ]
+    );
+    assert!(events[9].data.contains("/assets/image/"));
+    assert!(events[9].data.contains("a cute cat"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn write_code_tool_should_reply_with_markdown() -> Result<()> {
+    let provider = MockProvider::new("write hello world in rust")
+        .with_tool_call("write_code", json!({"prompt": "hello world in rust"}))
+        .with_reply("```rust\nfn main() {}\n```");
+    let app = TestApp::new(provider)?;
+    let mut events = app.connect_events().await?;
+
+    app.post_audio(b"fake audio").await?;
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(
+        labels(&events),
+        [
+            "processing:upload_audio",
+            "processing:transcrition",
+            "input_skeleton",
+            "input",
+            "processing:thinking",
+            "reply_skeleton",
+            "processing:write_code",
+            "reply_delta",
+            "complete",
+            "reply",
+            "processing:thinking",
+            "complete",
+        ]
+    );
+    assert!(events[9].data.contains("<pre"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn multiple_tool_calls_should_reply_in_separate_blocks() -> Result<()> {
+    let provider = MockProvider::new("draw a cat and write a poem about it")
+        .with_tool_call("draw_image", json!({"prompt": "a cute cat"}))
+        .with_tool_call("answer", json!({"prompt": "a poem about a cute cat"}))
+        .with_reply("Cats are very cute. They nap all day long.")
+        .with_summary("Here is your cat and the poem");
+    let app = TestApp::new(provider)?;
+    let mut events = app.connect_events().await?;
+
+    let res = app.post_audio(b"fake audio").await?;
+    assert_eq!(res, json!({"status": "done"}));
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(
+        labels(&events),
+        [
+            "processing:upload_audio",
+            "processing:transcrition",
+            "input_skeleton",
+            "input",
+            "processing:thinking",
+            "reply_skeleton",
+            "processing:draw_image",
+            "reply",
+            "complete",
+            "reply",
+            "reply_skeleton",
+            "processing:chat_completion",
+            "reply_delta",
+            "speech_segment",
+            "speech_segment",
+            "complete",
+            "reply",
+            "processing:thinking",
+            "reply_skeleton",
+            "complete",
+            "reply",
+        ]
+    );
+    assert!(events[9].data.contains("/assets/image/"));
+    assert!(events[16].data.contains("They nap all day long."));
+    assert!(events[20].data.contains("Here is your cat and the poem"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_tool_calls_should_be_told_to_the_model() -> Result<()> {
+    let provider = MockProvider::new("fly me to the moon")
+        .with_tool_call("fly", json!({"to": "the moon"}))
+        .with_tool_call("draw_image", json!({"subject": "the moon"}))
+        .with_summary("I can't fly, sorry");
+    let app = TestApp::new(provider)?;
+    let mut events = app.connect_events().await?;
+
+    let res = app.post_audio(b"fake audio").await?;
+    assert_eq!(res, json!({"status": "done"}));
+
+    let events = read_events(&mut events).await?;
+    let labels = labels(&events);
+    assert!(!labels.contains(&"error".to_string()));
+    assert!(!labels.contains(&"processing:draw_image".to_string()));
+    assert!(events
+        .last()
+        .unwrap()
+        .data
+        .contains("I can&#x27;t fly, sorry"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn plain_reply_should_be_spoken() -> Result<()> {
+    let app = TestApp::new(MockProvider::new("hi").with_reply("Hi there"))?;
+    let mut events = app.connect_events().await?;
+
+    app.post_audio(b"fake audio").await?;
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(
+        labels(&events),
+        [
+            "processing:upload_audio",
+            "processing:transcrition",
+            "input_skeleton",
+            "input",
+            "processing:thinking",
+            "reply_skeleton",
+            "processing:speech",
+            "reply",
+            "speech_segment",
+            "complete",
+            "reply",
+        ]
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn json_text_input_should_skip_transcription() -> Result<()> {
+    let app = TestApp::new(MockProvider::new("unused").with_reply("Hi there"))?;
+    let mut events = app.connect_events().await?;
+
+    let res = app.post_json(json!({"text": "hi from keyboard"})).await?;
+    assert_eq!(res, json!({"status": "done"}));
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(
+        labels(&events),
+        [
+            "input_skeleton",
+            "input",
+            "processing:thinking",
+            "reply_skeleton",
+            "processing:speech",
+            "reply",
+            "speech_segment",
+            "complete",
+            "reply",
+        ]
+    );
+    assert!(events[1].data.contains("hi from keyboard"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn multipart_text_input_should_skip_transcription() -> Result<()> {
+    let app = TestApp::new(MockProvider::new("unused").with_reply("Hi there"))?;
+    let mut events = app.connect_events().await?;
+
+    app.post_multipart("text", b"hi from form").await?;
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(
+        labels(&events)[..4],
+        [
+            "processing:upload_audio",
+            "input_skeleton",
+            "input",
+            "processing:thinking"
+        ]
+    );
+    assert!(events[2].data.contains("hi from form"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn empty_text_input_should_signal_error() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut events = app.connect_events().await?;
+
+    let (status, res) = app.try_post_json(json!({"text": "  "})).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(res["type"], "urn:ava:error:bad_input");
+    assert_eq!(res["detail"], "text input shall not be empty");
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(labels(&events), ["error"]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn missing_audio_should_signal_error() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut events = app.connect_events().await?;
+
+    let (status, res) = app.try_post_multipart("video", b"fake video").await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(res["status"], 400);
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(labels(&events), ["processing:upload_audio", "error"]);
+    assert!(events[1].data.contains("expected an audio or text field"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn rejected_image_should_be_reported_as_content_policy_violation() -> Result<()> {
+    let provider = MockProvider::default()
+        .with_tool_call("draw_image", json!({"prompt": "something nasty"}))
+        .with_image_error("400 Bad Request: content_policy_violation");
+    let app = TestApp::new(provider)?;
+    let mut events = app.connect_events().await?;
+
+    let (status, res) = app.try_post_json(json!({"text": "draw it"})).await?;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(res["type"], "urn:ava:error:content_policy");
+    // the upstream message stays on the server
+    assert!(!res["detail"].as_str().unwrap().contains("400"));
+
+    let events = read_events(&mut events).await?;
+    let error = events.last().unwrap();
+    assert_eq!(error.label(), "error");
+    assert!(error.data.contains("data-error=\"content_policy\""));
+    Ok(())
+}
+
+#[tokio::test]
+async fn errors_should_be_shown_in_the_preferred_language() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut events = app.connect_events().await?;
+
+    let req = app
+        .request("POST", "/assistant")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::ACCEPT_LANGUAGE, "zh-CN,zh;q=0.9,en;q=0.8")
+        .body(Body::from(json!({"text": ""}).to_string()))?;
+    let (status, _) = app.send(req).await?;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let events = read_events(&mut events).await?;
+    assert_eq!(labels(&events), ["error"]);
+    assert!(events[0].data.contains("抱歉，无法理解这个请求。"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn finished_turn_should_show_in_index_page() -> Result<()> {
+    let app = TestApp::new(MockProvider::default().with_reply("Hi, nice to meet you"))?;
+    let _events = app.connect_events().await?;
+    app.post_audio(b"fake audio").await?;
+
+    let res = app
+        .app
+        .clone()
+        .oneshot(app.request("GET", "/").body(Body::empty())?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let body = String::from_utf8(body.to_vec())?;
+    assert!(body.contains("Hello Ava"));
+    assert!(body.contains("Hi, nice to meet you"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn reconnecting_client_should_receive_missed_events() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut events = app.connect_events().await?;
+    let res = app.post_audio(b"fake audio").await?;
+    assert_eq!(res, json!({"status": "done"}));
+    let events = read_events(&mut events).await?;
+    let ids: Vec<u64> = events.iter().map(|e| e.id.parse().unwrap()).collect();
+    assert!(ids.windows(2).all(|w| w[1] == w[0] + 1));
+    // input and reply of the turn update the blocks of the turn
+    assert!(!events[3].target.is_empty());
+    assert_eq!(events[3].target, events[7].target);
+
+    // the connection broke right after the third event
+    let mut replayed = app.reconnect_events(&events[2].id).await?;
+    let replayed = read_events(&mut replayed).await?;
+    assert_eq!(labels(&replayed), labels(&events[3..]));
+    assert_eq!(replayed[0].id, events[3].id);
+
+    // without Last-Event-ID only new events are received
+    let mut fresh = app.connect_events().await?;
+    assert!(read_events(&mut fresh).await?.is_empty());
+    Ok(())
+}
+
+#[tokio::test]
+async fn posting_before_connecting_should_keep_events_for_replay() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let res = app.post_json(json!({"text": "hello"})).await?;
+    assert_eq!(res, json!({"status": "done"}));
+
+    let mut events = app.connect_events().await?;
+    let events = read_events(&mut events).await?;
+    assert_eq!(labels(&events)[0], "input_skeleton");
+    assert!(events[1].data.contains("hello"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_should_take_streamed_audio_and_push_events() -> Result<()> {
+    let app = TestApp::new(MockProvider::default().with_reply("Hi, nice to meet you"))?;
+    let mut ws = app.connect_ws().await?;
+
+    // the recording is streamed while speaking
+    for chunk in [&b"fake "[..], b"audio"] {
+        ws.send(Message::Binary(chunk.to_vec())).await?;
+    }
+    let end = json!({"type": "audio_end", "duration": 1.5});
+    ws.send(Message::Text(end.to_string())).await?;
+
+    let events = read_frames(&mut ws).await?;
+    assert_eq!(
+        labels(&events),
+        [
+            "processing:transcrition",
+            "input_skeleton",
+            "input",
+            "processing:thinking",
+            "reply_skeleton",
+            "processing:chat_completion",
+            "reply_delta",
+            "speech_segment",
+            "complete",
+            "reply",
+            "processing:thinking",
+            "complete",
+        ]
+    );
+    assert!(events[2].data.contains("Hello Ava"));
+    assert_eq!(events[6].data, "Hi, nice to meet you");
+    assert_eq!(events[6].target, events[2].target);
+
+    // the same device on /events gets them too
+    let mut sse = app.reconnect_events("0").await?;
+    assert_eq!(read_events(&mut sse).await?.len(), events.len());
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_should_signal_invalid_messages() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut ws = app.connect_ws().await?;
+
+    ws.send(Message::Text("hello".to_string())).await?;
+    let text = json!({"type": "text", "text": " "});
+    ws.send(Message::Text(text.to_string())).await?;
+
+    let events = read_frames(&mut ws).await?;
+    assert_eq!(labels(&events), ["error", "error"]);
+    assert!(events[0].data.contains("invalid message"));
+    assert!(events[1].data.contains("text input shall not be empty"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_should_refuse_empty_recording_like_missing_upload() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut ws = app.connect_ws().await?;
+
+    let end = json!({"type": "audio_end"});
+    ws.send(Message::Text(end.to_string())).await?;
+
+    let events = read_frames(&mut ws).await?;
+    assert_eq!(labels(&events), ["error"]);
+    assert!(events[0].data.contains("expected an audio or text field"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_should_refuse_pages_of_other_sites() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let addr = app.serve()?;
+    let connect = |cookie: String, origin: Option<String>| {
+        let mut req = format!("ws://{addr}/ws").into_client_request()?;
+        let headers = req.headers_mut();
+        headers.insert(header::COOKIE, cookie.parse()?);
+        if let Some(origin) = origin {
+            headers.insert(header::ORIGIN, origin.parse()?);
+        }
+        anyhow::Ok(tokio_tungstenite::connect_async(req))
+    };
+    let device = format!("device_id={}", app.device_id);
+    let session = format!("{device}; session=any");
+
+    let evil = Some("https://evil.example".to_string());
+    assert!(connect(device.clone(), evil)?.await.is_err());
+    let ours = Some(format!("http://{addr}"));
+    assert!(connect(device.clone(), ours)?.await.is_ok());
+    // other clients may go without it, unless signed in
+    assert!(connect(device, None)?.await.is_ok());
+    assert!(connect(session, None)?.await.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_should_close_with_reason_on_shutdown() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut ws = app.connect_ws().await?;
+    // the socket is subscribed to the events once it's served
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    app.state.shutdown(Duration::from_secs(1)).await;
+    let (labels, reason) = read_until_close(&mut ws).await?;
+    assert_eq!(labels, ["signal"]);
+    assert_eq!(reason.as_deref(), Some("the server is shutting down"));
+
+    // a turn asked for while stopping is refused the same way
+    let mut ws = app.connect_ws().await?;
+    let text = json!({"type": "text", "text": "hello"});
+    ws.send(Message::Text(text.to_string())).await?;
+    let (labels, reason) = read_until_close(&mut ws).await?;
+    assert!(labels.is_empty());
+    assert_eq!(reason.as_deref(), Some("the server is shutting down"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn websocket_should_close_once_signed_out() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let req = app
+        .request("POST", "/signup")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from("username=alice&password=correct+horse"))?;
+    let res = app.app.clone().oneshot(req).await?;
+    let session = set_cookie(&res, "session").unwrap();
+    let cookie = format!("device_id={}; session={}", app.device_id, session);
+    let mut ws = app.connect_ws_with(&cookie).await?;
+
+    let req = Request::post("/logout")
+        .header(header::COOKIE, &cookie)
+        .body(Body::empty())?;
+    app.app.clone().oneshot(req).await?;
+
+    let text = json!({"type": "text", "text": "hello"});
+    ws.send(Message::Text(text.to_string())).await?;
+    let (labels, reason) = read_until_close(&mut ws).await?;
+    assert!(labels.is_empty());
+    assert_eq!(reason.as_deref(), Some("sign in again to use this device"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn shutdown_should_notify_clients_and_reject_new_requests() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let mut events = app.connect_events().await?;
+
+    app.state.shutdown(Duration::from_secs(1)).await;
+
+    let labels = labels(&read_events(&mut events).await?);
+    assert_eq!(labels, ["shutdown"]);
+    // the event stream ends after the shutdown signal
+    assert!(events.data().await.is_none());
+
+    let req = app
+        .request("POST", "/assistant")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({"text": "hello"}).to_string()))?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_storage_should_report_usage_per_device() -> Result<()> {
+    let app = TestApp::new(MockProvider::default().with_reply("Hi, nice to meet you"))?;
+    let _events = app.connect_events().await?;
+    app.post_audio(b"fake audio").await?;
+
+    let req = Request::get("/admin/storage").body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    let req = Request::get("/admin/storage")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}x"))
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+    let req = Request::get("/admin/storage")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let report: Value = serde_json::from_slice(&body)?;
+    assert_eq!(report["devices"][0]["device_id"], app.device_id);
+    assert_eq!(report["devices"][0]["files"], 1);
+    assert_eq!(report["files"], 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn admin_presence_should_list_connected_devices() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let first = app.connect_events().await?;
+    let _second = app.connect_events().await?;
+
+    let report = app.admin_get("/admin/presence").await?;
+    assert_eq!(report["online"], 1);
+    assert_eq!(report["connections"], 2);
+    assert_eq!(report["devices"][0]["device_id"], app.device_id);
+
+    drop(first);
+    let report = app.admin_get("/admin/presence").await?;
+    assert_eq!(report["devices"][0]["connections"], 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn assets_should_only_be_served_to_owner() -> Result<()> {
+    let app = TestApp::new(MockProvider::default().with_reply("Hi, nice to meet you"))?;
+    let mut events = app.connect_events().await?;
+    app.post_audio(b"fake audio").await?;
+    let events = read_events(&mut events).await?;
+    let url = &events[8].data;
+    assert!(url.starts_with("/assets/audio/"));
+
+    let res = app
+        .app
+        .clone()
+        .oneshot(app.request("GET", url).body(Body::empty())?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let other = Request::get(url)
+        .header(header::COOKIE, format!("device_id={}", Uuid::new_v4()))
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(other).await?;
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+    // only audio and images are served, not e.g. the history database
+    let url = format!("/assets/history/{}/conf", app.device_id);
+    let res = app
+        .app
+        .clone()
+        .oneshot(app.request("GET", &url).body(Body::empty())?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    Ok(())
+}
+
+#[tokio::test]
+async fn invalid_device_id_should_be_rejected() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let req = Request::get("/events")
+        .header(header::COOKIE, "device_id=../../etc")
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    // the index page replaces it with a new one
+    let req = Request::get("/")
+        .header(header::COOKIE, "device_id=../../etc")
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookie = res.headers()[header::SET_COOKIE].to_str()?;
+    let device_id = cookie
+        .strip_prefix("device_id=")
+        .and_then(|v| v.split(';').next())
+        .unwrap();
+    assert!(device_id.parse::<DeviceId>().is_ok());
+    // it's the identity of the user, scripts and other sites don't get it
+    assert!(cookie.contains("HttpOnly"));
+    assert!(cookie.contains("SameSite=Lax"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn history_and_assets_should_follow_signed_in_user() -> Result<()> {
+    let app = TestApp::new(MockProvider::default().with_reply("Hi, nice to meet you"))?;
+    let mut events = app.connect_events().await?;
+    app.post_audio(b"fake audio").await?;
+    let url = read_events(&mut events).await?[8].data.clone();
+
+    // signing up on the device links it to the account
+    let req = app
+        .request("POST", "/signup")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from("username=alice&password=correct+horse"))?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    let session = set_cookie(&res, "session").unwrap();
+    let req = Request::get("/")
+        .header(
+            header::COOKIE,
+            format!("device_id={}; session={}", app.device_id, session),
+        )
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // another browser signed in as the same user gets a new device, which
+    // sees the history and assets of the first one
+    let req = Request::get("/")
+        .header(header::COOKIE, format!("session={}", session))
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    let other = set_cookie(&res, "device_id").unwrap();
+    assert_ne!(other, app.device_id);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    assert!(String::from_utf8(body.to_vec())?.contains("Hi, nice to meet you"));
+    let req = Request::get(&url)
+        .header(
+            header::COOKIE,
+            format!("device_id={}; session={}", other, session),
+        )
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+
+    // once linked, the device can't be used without signing in
+    let req = app.request("GET", "/events").body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    Ok(())
+}
+
+#[tokio::test]
+async fn login_should_reject_wrong_password() -> Result<()> {
+    let app = TestApp::new(MockProvider::default())?;
+    let form = |path: &str, body: &'static str| {
+        Request::post(path)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(body))
+    };
+    let res = app
+        .app
+        .clone()
+        .oneshot(form("/signup", "username=bob&password=short")?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    let res = app
+        .app
+        .clone()
+        .oneshot(form("/signup", "username=bob&password=long+enough")?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+
+    let res = app
+        .app
+        .clone()
+        .oneshot(form("/login", "username=bob&password=wrong+password")?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    assert!(set_cookie(&res, "session").is_none());
+    let res = app
+        .app
+        .clone()
+        .oneshot(form("/login", "username=BOB&password=long+enough")?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::SEE_OTHER);
+    assert!(set_cookie(&res, "session").is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn requests_over_limit_should_be_refused() -> Result<()> {
+    let app = TestApp::with_config(MockProvider::default(), |config| {
+        config.limits.requests_per_minute = 1;
+    })?;
+    let mut events = app.connect_events().await?;
+    let res = app.post_json(json!({"text": "hello"})).await?;
+    assert_eq!(res, json!({"status": "done"}));
+    read_events(&mut events).await?;
+
+    let req = app
+        .request("POST", "/assistant")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({"text": "hello again"}).to_string()))?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        res.headers()[header::CONTENT_TYPE],
+        "application/problem+json"
+    );
+    assert!(res.headers().contains_key(header::RETRY_AFTER));
+    let events = read_events(&mut events).await?;
+    assert_eq!(labels(&events), ["error"]);
+    assert!(events[0].data.contains("too fast"));
+
+    let req = app.request("GET", "/usage").body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let usage: Value = serde_json::from_slice(&body)?;
+    assert_eq!(usage["key"], format!("device:{}", app.device_id));
+    assert_eq!(usage["requests_last_minute"], 1);
+    assert!(usage["spent_today"].as_f64().unwrap() > 0.0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn anonymous_limits_should_follow_the_client_address() -> Result<()> {
+    let app = TestApp::with_config(MockProvider::default(), |config| {
+        config.limits.requests_per_minute = 1;
+    })?;
+    let addr = SocketAddr::from(([203, 0, 113, 7], 4000));
+    let post = |device_id: &str| -> Result<Request<Body>> {
+        let mut req = Request::post("/assistant")
+            .header(header::COOKIE, format!("device_id={}", device_id))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(json!({"text": "hello"}).to_string()))?;
+        req.extensions_mut().insert(ConnectInfo(addr));
+        Ok(req)
+    };
+
+    let res = app.app.clone().oneshot(post(&app.device_id)?).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    // a fresh cookie from the same address doesn't reset the limit
+    let res = app
+        .app
+        .clone()
+        .oneshot(post(&DeviceId::new().to_string())?)
+        .await?;
+    assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    Ok(())
+}
+
+#[tokio::test]
+async fn turn_usage_should_be_shown_and_reported_per_tool() -> Result<()> {
+    let provider = MockProvider::new("draw a cat")
+        .with_tool_call("draw_image", json!({"prompt": "a cute cat"}));
+    let app = TestApp::with_config(provider, |config| {
+        config.assistant.show_usage = true;
+    })?;
+    let mut events = app.connect_events().await?;
+    app.post_audio(b"fake audio").await?;
+
+    let events = read_events(&mut events).await?;
+    let labels = labels(&events);
+    assert_eq!(labels[labels.len() - 2..], ["reply_skeleton", "reply"]);
+    let usage = &events.last().unwrap().data;
+    assert!(usage.contains("<details"));
+    assert!(usage.contains("4 calls"));
+    assert!(usage.contains("1 image(s)"));
+
+    let req = Request::get("/admin/usage?days=1")
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let report: Value = serde_json::from_slice(&body)?;
+    let tools = report["tools"].as_array().unwrap();
+    let tool = |name: &str| tools.iter().find(|v| v["tool"] == name).unwrap();
+    assert_eq!(tool("draw_image")["images"], 1);
+    // transcription and two rounds of picking the tools
+    assert_eq!(tool("assistant")["calls"], 3);
+    assert_eq!(
+        report["spend_today"][0]["key"],
+        format!("device:{}", app.device_id)
+    );
+
+    let req = Request::get(format!("/admin/usage?days={}", u32::MAX))
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+        .body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let report: Value = serde_json::from_slice(&body)?;
+    assert_eq!(report["days"], 366);
+    Ok(())
+}
+
+#[tokio::test]
+async fn metrics_should_report_steps_tools_and_connections() -> Result<()> {
+    let provider = MockProvider::new("draw a cat")
+        .with_tool_call("draw_image", json!({"prompt": "a cute cat"}));
+    let app = TestApp::new(provider)?;
+    let _events = app.connect_events().await?;
+    app.post_audio(b"fake audio").await?;
+
+    let req = Request::get("/metrics").body(Body::empty())?;
+    let res = app.app.clone().oneshot(req).await?;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = hyper::body::to_bytes(res.into_body()).await?;
+    let text = std::str::from_utf8(&body)?;
+    for line in [
+        "ava_steps_total{step=\"upload_audio\"} 1",
+        "ava_steps_total{step=\"transcrition\"} 1",
+        "ava_steps_total{step=\"thinking\"} 2",
+        "ava_steps_total{step=\"draw_image\"} 1",
+        "ava_tool_calls_total{tool=\"draw_image\"} 1",
+        "ava_llm_requests_total{capability=\"tools\"} 2",
+        "ava_sse_connections 1",
+    ] {
+        assert!(text.contains(line), "missing {line}");
+    }
+    assert!(text.contains("ava_asset_bytes_written_total{kind=\"image\"}"));
+    Ok(())
+}
+
+struct TestApp {
+    app: Router,
+    state: Arc<AppState>,
+    device_id: String,
+}
+
+#[derive(Debug, Default)]
+struct SseEvent {
+    id: String,
+    event: String,
+    /// id of the block the event updates
+    target: String,
+    data: String,
+}
+
+impl TestApp {
+    fn new(provider: MockProvider) -> Result<Self> {
+        Self::with_config(provider, |_| {})
+    }
+
+    fn with_config(provider: MockProvider, f: impl FnOnce(&mut AppConfig)) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("ava-bot-test-{}", Uuid::new_v4()));
+        let mut config = AppConfig::default();
+        config.storage.assets_dir = dir.join("assets");
+        config.storage.db_path = dir.join("history");
+        config.server.admin_token = Some(ADMIN_TOKEN.to_string());
+        f(&mut config);
+        let llm = LlmProviders::from_provider(Arc::new(provider));
+        let state = Arc::new(AppState::with_llm(config, llm)?);
+        Ok(Self {
+            app: router(state.clone()),
+            state,
+            device_id: Uuid::new_v4().to_string(),
+        })
+    }
+
+    fn request(&self, method: &str, uri: &str) -> request::Builder {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::COOKIE, format!("device_id={}", self.device_id))
+    }
+
+    async fn connect_events(&self) -> Result<BoxBody> {
+        let req = self.request("GET", "/events").body(Body::empty())?;
+        self.subscribe(req).await
+    }
+
+    /// reconnect like EventSource does, after the event of `last_id`
+    async fn reconnect_events(&self, last_id: &str) -> Result<BoxBody> {
+        let req = self
+            .request("GET", "/events")
+            .header("last-event-id", last_id)
+            .body(Body::empty())?;
+        self.subscribe(req).await
+    }
+
+    async fn admin_get(&self, uri: &str) -> Result<Value> {
+        let req = Request::get(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_TOKEN}"))
+            .body(Body::empty())?;
+        let (status, res) = self.send(req).await?;
+        assert_eq!(status, StatusCode::OK);
+        Ok(res)
+    }
+
+    async fn connect_ws(&self) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        self.connect_ws_with(&format!("device_id={}", self.device_id))
+            .await
+    }
+
+    async fn connect_ws_with(
+        &self,
+        cookie: &str,
+    ) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>> {
+        let addr = self.serve()?;
+        let mut req = format!("ws://{addr}/ws").into_client_request()?;
+        let headers = req.headers_mut();
+        headers.insert(header::COOKIE, cookie.parse()?);
+        // like a browser on the page served by the app
+        headers.insert(header::ORIGIN, format!("http://{addr}").parse()?);
+        let (ws, _) = tokio_tungstenite::connect_async(req).await?;
+        Ok(ws)
+    }
+
+    /// serve the app on a local port, websockets can't be tested in memory
+    fn serve(&self) -> Result<SocketAddr> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let server = axum::Server::from_tcp(listener)?.serve(self.app.clone().into_make_service());
+        tokio::spawn(server);
+        Ok(addr)
+    }
+
+    async fn subscribe(&self, req: Request<Body>) -> Result<BoxBody> {
+        let res = self.app.clone().oneshot(req).await?;
+        assert_eq!(res.status(), StatusCode::OK);
+        Ok(res.into_body())
+    }
+
+    async fn post_audio(&self, data: &[u8]) -> Result<Value> {
+        self.post_multipart("audio", data).await
+    }
+
+    async fn post_multipart(&self, name: &str, data: &[u8]) -> Result<Value> {
+        let (status, res) = self.try_post_multipart(name, data).await?;
+        assert_eq!(status, StatusCode::OK);
+        Ok(res)
+    }
+
+    async fn try_post_multipart(&self, name: &str, data: &[u8]) -> Result<(StatusCode, Value)> {
+        let mut body = format!(
+            "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; filename=\"{name}.mp3\"\r\nContent-Type: audio/mp3\r\n\r\n"
+        )
+        .into_bytes();
+        body.extend_from_slice(data);
+        body.extend_from_slice(format!("\r\n--{BOUNDARY}--\r\n").as_bytes());
+
+        let req = self
+            .request("POST", "/assistant")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))?;
+        self.send(req).await
+    }
+
+    async fn post_json(&self, body: Value) -> Result<Value> {
+        let (status, res) = self.try_post_json(body).await?;
+        assert_eq!(status, StatusCode::OK);
+        Ok(res)
+    }
+
+    async fn try_post_json(&self, body: Value) -> Result<(StatusCode, Value)> {
+        let req = self
+            .request("POST", "/assistant")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))?;
+        self.send(req).await
+    }
+
+    async fn send(&self, req: Request<Body>) -> Result<(StatusCode, Value)> {
+        let res = self.app.clone().oneshot(req).await?;
+        let status = res.status();
+        let body = hyper::body::to_bytes(res.into_body()).await?;
+        Ok((status, serde_json::from_slice(&body)?))
+    }
+}
+
+impl SseEvent {
+    fn parse(block: &str) -> Option<Self> {
+        let mut event = SseEvent::default();
+        let mut data = vec![];
+        for line in block.lines() {
+            if let Some(v) = line.strip_prefix("id:") {
+                event.id = v.trim_start().to_string();
+            } else if let Some(v) = line.strip_prefix("event:") {
+                event.event = v.trim_start().to_string();
+            } else if let Some(v) = line.strip_prefix("data:") {
+                data.push(v.strip_prefix(' ').unwrap_or(v));
+            }
+        }
+        event.data = data.join("\n");
+        // events updating a block wrap their data with the target, like the page
+        if let Ok(Value::Object(v)) = serde_json::from_str(&event.data) {
+            if let (Some(Value::String(target)), Some(Value::String(data))) =
+                (v.get("target"), v.get("data"))
+            {
+                event.target = target.clone();
+                event.data = data.clone();
+            }
+        }
+        (!event.event.is_empty()).then_some(event)
+    }
+
+    // signals are labeled by their state, other events by their name
+    fn label(&self) -> String {
+        if self.event != "signal" {
+            return self.event.clone();
+        }
+        if let Some(step) = self.data.split("Processing ").nth(1) {
+            format!(
+                "processing:{}",
+                step.split('<').next().unwrap_or_default().trim()
+            )
+        } else if self.data.contains("Complete") {
+            "complete".to_string()
+        } else if self.data.contains("data-error") {
+            "error".to_string()
+        } else if self.data.contains("restarting") {
+            "shutdown".to_string()
+        } else {
+            self.data.clone()
+        }
+    }
+}
+
+/// Read all the events sent so far, it stops once the stream is idle.
+async fn read_events(body: &mut BoxBody) -> Result<Vec<SseEvent>> {
+    let mut buf = String::new();
+    while let Ok(Some(chunk)) = timeout(Duration::from_millis(200), body.data()).await {
+        buf.push_str(std::str::from_utf8(&chunk?)?);
+    }
+    Ok(buf.split("\n\n").filter_map(SseEvent::parse).collect())
+}
+
+/// Read all the events pushed over the websocket so far, it stops once the
+/// socket is idle.
+async fn read_frames(ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>) -> Result<Vec<SseEvent>> {
+    let mut events = vec![];
+    while let Ok(Some(frame)) = timeout(Duration::from_millis(200), ws.next()).await {
+        let Message::Text(text) = frame? else {
+            continue;
+        };
+        let v: Value = serde_json::from_str(&text)?;
+        events.push(SseEvent {
+            id: v["id"].to_string(),
+            event: v["event"].as_str().unwrap_or_default().to_string(),
+            target: v["target"].as_str().unwrap_or_default().to_string(),
+            data: v["data"].as_str().unwrap_or_default().to_string(),
+        });
+    }
+    Ok(events)
+}
+
+/// event names of the frames before the close frame, and its reason
+async fn read_until_close(
+    ws: &mut WebSocketStream<MaybeTlsStream<TcpStream>>,
+) -> Result<(Vec<String>, Option<String>)> {
+    let mut labels = vec![];
+    loop {
+        match timeout(Duration::from_secs(1), ws.next()).await? {
+            Some(Ok(Message::Text(text))) => {
+                let v: Value = serde_json::from_str(&text)?;
+                labels.push(v["event"].as_str().unwrap_or_default().to_string());
+            }
+            Some(Ok(Message::Close(frame))) => {
+                return Ok((labels, frame.map(|v| v.reason.to_string())))
+            }
+            v => anyhow::bail!("expect a close frame, got {v:?}"),
+        }
+    }
+}
+
+/// value of the cookie set by the response
+fn set_cookie<B>(res: &Response<B>, name: &str) -> Option<String> {
+    res.headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .find_map(|v| v.strip_prefix(name)?.strip_prefix('='))
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.to_string())
+}
+
+fn labels(events: &[SseEvent]) -> Vec<String> {
+    events.iter().map(|e| e.label()).collect()
+}
@@ -0,0 +1,11 @@
+pub mod chat_completion;
+pub mod create_image;
+pub mod embedding;
+pub mod speech;
+pub mod whisper;
+
+pub use chat_completion::*;
+pub use create_image::*;
+pub use embedding::*;
+pub use speech::*;
+pub use whisper::*;